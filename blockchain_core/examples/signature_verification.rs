@@ -0,0 +1,82 @@
+//! Measures how many transaction signatures per second block validation
+//! checks: serially, in parallel on the rayon thread pool, and in parallel
+//! with every transaction already in the signature cache.
+//!
+//! `cargo run --release -p blockchain_core --example signature_verification [transactions] [rounds]`
+
+use blockchain_core::blockchain::{
+    AccountKeys, Address, Block, BlockHash, Coinbase, Recipient, Sender, SignatureCache,
+    SignedTransaction, Transaction,
+};
+use primitive_types::U256;
+use std::time::{Duration, Instant};
+
+const CHAIN_ID: u64 = 1;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let transaction_count: usize = args.next().map_or(2000, |arg| arg.parse().expect("transaction count"));
+    let rounds: usize = args.next().map_or(5, |arg| arg.parse().expect("round count"));
+
+    let transactions: Vec<SignedTransaction> = (0..transaction_count)
+        .map(|index| {
+            let mut sender = AccountKeys::new();
+            let transaction = Transaction {
+                from: Sender::Key(sender.get_public_key()),
+                to: Recipient::Account(AccountKeys::new().get_address()),
+                amount: U256::from(index + 1),
+                fee: U256::one(),
+                nonce: 0,
+            };
+            let signature = sender.sign_transaction(&transaction, CHAIN_ID);
+            SignedTransaction::new(transaction, signature)
+        })
+        .collect();
+    let block = Block::create_block(
+        0,
+        0,
+        BlockHash::GENESIS_PARENT,
+        1,
+        U256::MAX,
+        &transactions,
+        Coinbase::new(Address::default(), U256::zero(), &transactions),
+    );
+    let mut cache = SignatureCache::new(transaction_count);
+    for signed_transaction in transactions.iter() {
+        cache.insert(signed_transaction, CHAIN_ID);
+    }
+
+    println!(
+        "{} signatures, best of {} rounds, {} rayon threads",
+        transaction_count,
+        rounds,
+        rayon::current_num_threads()
+    );
+    let serial = best_of(rounds, || block.validate_signatures_serially(CHAIN_ID).unwrap());
+    report("serial", transaction_count, serial, serial);
+    let parallel = best_of(rounds, || block.validate_signatures(CHAIN_ID, None).unwrap());
+    report("parallel", transaction_count, parallel, serial);
+    let cached = best_of(rounds, || block.validate_signatures(CHAIN_ID, Some(&cache)).unwrap());
+    report("parallel, cached", transaction_count, cached, serial);
+}
+
+fn best_of(rounds: usize, mut validate: impl FnMut()) -> Duration {
+    (0..rounds.max(1))
+        .map(|_| {
+            let start = Instant::now();
+            validate();
+            start.elapsed()
+        })
+        .min()
+        .expect("At least one round")
+}
+
+fn report(path: &str, transaction_count: usize, elapsed: Duration, serial: Duration) {
+    println!(
+        "{:>17}: {:>9.1} ms, {:>9.0} signatures/s, {:>5.2}x serial",
+        path,
+        elapsed.as_secs_f64() * 1000.0,
+        transaction_count as f64 / elapsed.as_secs_f64(),
+        serial.as_secs_f64() / elapsed.as_secs_f64()
+    );
+}
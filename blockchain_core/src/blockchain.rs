@@ -1,359 +1,761 @@
 pub mod account;
+pub mod address;
 pub mod block;
+pub mod difficulty;
+pub mod emission;
+pub mod encoding;
+pub mod error;
+pub mod hash;
+pub mod header_chain;
+pub mod light_client;
+pub mod multisig;
+pub mod orphan;
+pub mod pow;
+pub mod signature_cache;
+pub mod spec;
+pub mod state;
+pub mod state_tree;
 pub mod utils;
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
 
 pub use account::AccountKeys;
-use block::MerkleTree;
-pub use block::{Block, Header, Transaction};
-use k256::{PublicKey};
-use log;
+pub use address::{Address, AddressError};
+pub use block::{
+    verify_proof, Block, Coinbase, Header, MerkleProof, MerkleTree, Recipient, Sender,
+    SignedTransaction, Transaction, BLOCK_VERSION,
+};
+pub use difficulty::{
+    Asert, BlockTiming, DifficultyAdjustment, DifficultyAlgorithm, Lwma, PeriodRetarget,
+    RetargetContext, StepAdjustment, ANCHOR_HEIGHT,
+};
+use header_chain::validate_proof_of_work;
+pub use emission::EmissionSchedule;
+pub use encoding::{Decode, DecodeError, Encode};
+pub use error::{BlockError, TimestampError, TransactionError};
+pub use hash::{BlockHash, MerkleHash, SignatureCacheKey, StateHash, TxHash};
+pub use header_chain::{HeaderChain, HeaderRules};
+pub use light_client::LightClient;
+pub use multisig::{MultisigPolicy, MAX_MULTISIG_KEYS};
+pub use orphan::OrphanPool;
+pub use pow::{PowEngine, PowSolution};
+pub use signature_cache::SignatureCache;
+pub use spec::{ChainSpec, ChainSpecError, GenesisAllocation};
+pub use state::{BlockUndo, StateOverlay};
+pub use state_tree::{AccountProof, StateTree};
+use crate::log;
 use multimap::MultiMap;
 use primitive_types::U256;
-use uint::FromStrRadixErr;
-use utils::convert_public_key_to_bytes;
-use k256::elliptic_curve::sec1::ToEncodedPoint; 
+use utils::hash_transaction;
 use serde::{Serialize, Deserialize};
 use serde_json_any_key::*;
 
+/// Number of ancestors whose median timestamp a new block must exceed.
+pub const MEDIAN_TIME_PAST_WINDOW: usize = 11;
+/// How far, in seconds, a block timestamp may run ahead of local time.
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blockchain {
-    pub hash_to_block: HashMap<String, Block>,
-    pub hash_to_miner: HashMap<String, Vec<u8>>,
-    pub block_parent_map: HashMap<String, String>,
-    pub parent_block_map: HashMap<String, String>,
-    pub hash_to_cumulative_difficulty: HashMap<String, U256>,
-    pub cumulative_difficulty_to_hash: MultiMap<U256, String>,
-    pub difficulty: U256,
+    pub chain_id: u64,
+    /// Human-readable part of the chain's bech32 addresses.
+    pub address_prefix: String,
+    pub hash_to_block: HashMap<BlockHash, Block>,
+    pub hash_to_miner: HashMap<BlockHash, Address>,
+    pub block_parent_map: HashMap<BlockHash, BlockHash>,
+    pub parent_block_map: HashMap<BlockHash, BlockHash>,
+    pub hash_to_cumulative_work: HashMap<BlockHash, U256>,
+    pub cumulative_work_to_hash: MultiMap<U256, BlockHash>,
+    /// Target of the first block after genesis, set by the chain spec.
+    initial_difficulty: U256,
     pub target_duration_between_blocks: u64,
-    pub latest_block_timestamp: u64,
     pub max_transactions_per_block: usize,
     #[serde(with = "any_key_map")]
-    pub accounts: HashMap<Vec<u8>, AccountState>,
-    pub mining_reward: U256,
-    pub current_longest_chain_latest_block_hash: String,
-    pub blocks_between_difficulty_adjustment: u64,
-    pub latest_n_block_timestamps: Vec<u64>,
-    pub hash_to_miners_who_received_the_block: HashMap<String, Vec<Vec<u8>>>,
+    pub accounts: HashMap<Address, AccountState>,
+    /// State tree over `accounts`, updated with every change committed to
+    /// them, so a state root only rehashes the accounts a block touches.
+    state_tree: StateTree,
+    pub emission: EmissionSchedule,
+    /// Coins credited by the genesis allocations.
+    pub genesis_supply: U256,
+    /// Coins minted by the coinbases from height 1 up to each block, so a
+    /// reward never needs the chain replayed from genesis.
+    pub hash_to_issued: HashMap<BlockHash, U256>,
+    pub current_longest_chain_latest_block_hash: BlockHash,
+    pub difficulty_algorithm: DifficultyAdjustment,
+    pub median_time_past_window: usize,
+    pub max_future_block_time: u64,
+    pub hash_to_miners_who_received_the_block: HashMap<BlockHash, Vec<Address>>,
+    pub invalid_blocks: HashSet<BlockHash>,
+    pub orphans: OrphanPool,
+    /// Transactions whose signatures the mempool verified, skipped by block
+    /// validation. `None` verifies every signature of every block. It only
+    /// vouches for what this node verified, so it is never serialized and a
+    /// deserialized chain starts with an empty one.
+    #[serde(skip, default = "default_signature_cache")]
+    pub signature_cache: Option<SignatureCache>,
+    pub block_undo: HashMap<BlockHash, BlockUndo>,
+    /// Hashes of the canonical chain, indexed by block height.
+    pub canonical_chain: Vec<BlockHash>,
+    /// Block and position of every transaction on the canonical chain,
+    /// kept in step with `canonical_chain`.
+    pub tx_index: HashMap<TxHash, (BlockHash, usize)>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_signature_cache() -> Option<SignatureCache> {
+    Some(SignatureCache::default())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct AccountState {
     pub balance: U256,
     pub nonce: u128,
+    /// Key set and threshold of a registered multisig account.
+    pub multisig: Option<MultisigPolicy>,
 }
 
 impl Blockchain {
-    pub fn create_blockchain(
-        difficulty: U256,
-        target_duration_between_blocks: u64,
-        max_transactions_per_block: usize,
-        blocks_between_difficulty_adjustment: u64,
-    ) -> Self {
-        let mut hash_to_cumulative_difficulty = HashMap::new();
-        let mut cumulative_difficulty_to_hash = MultiMap::new();
-        hash_to_cumulative_difficulty.insert(String::from(""), U256::zero());
-        cumulative_difficulty_to_hash.insert(U256::zero(), String::from(""));
-        Self {
-            hash_to_block: HashMap::new(),
+    /// Builds the chain described by `spec`, starting from its genesis
+    /// block with the spec's allocations already credited.
+    pub fn from_spec(spec: &ChainSpec) -> Result<Self, ChainSpecError> {
+        spec.validate()?;
+        let genesis_block = spec.genesis_block();
+        let genesis_hash = Block::hash_header(&genesis_block.header);
+        let genesis_work = genesis_block.header.work();
+
+        let mut hash_to_cumulative_work = HashMap::new();
+        let mut cumulative_work_to_hash = MultiMap::new();
+        hash_to_cumulative_work.insert(BlockHash::GENESIS_PARENT, U256::zero());
+        cumulative_work_to_hash.insert(U256::zero(), BlockHash::GENESIS_PARENT);
+        hash_to_cumulative_work.insert(genesis_hash, genesis_work);
+        cumulative_work_to_hash.insert(genesis_work, genesis_hash);
+        let genesis_supply = spec
+            .allocations
+            .iter()
+            .fold(U256::zero(), |total, allocation| total + allocation.balance);
+        let accounts = spec.genesis_accounts();
+        let state_tree = StateTree::from_accounts(&accounts);
+        Ok(Self {
+            chain_id: spec.chain_id,
+            address_prefix: spec.address_prefix.clone(),
+            hash_to_block: HashMap::from([(genesis_hash, genesis_block)]),
             hash_to_miner: HashMap::new(),
-            block_parent_map: HashMap::new(),
-            parent_block_map: HashMap::new(),
-            hash_to_cumulative_difficulty,
-            cumulative_difficulty_to_hash,
-            difficulty,
-            target_duration_between_blocks,
-            latest_block_timestamp: 0,
-            max_transactions_per_block,
-            accounts: HashMap::new(),
-            mining_reward: U256::from(1000),
-            current_longest_chain_latest_block_hash: String::from(""),
-            blocks_between_difficulty_adjustment,
-            latest_n_block_timestamps: Vec::new(),
+            block_parent_map: HashMap::from([(genesis_hash, BlockHash::GENESIS_PARENT)]),
+            parent_block_map: HashMap::from([(BlockHash::GENESIS_PARENT, genesis_hash)]),
+            hash_to_cumulative_work,
+            cumulative_work_to_hash,
+            initial_difficulty: spec.initial_difficulty,
+            target_duration_between_blocks: spec.target_duration_between_blocks,
+            max_transactions_per_block: spec.max_transactions_per_block,
+            accounts,
+            state_tree,
+            emission: spec.emission.clone(),
+            genesis_supply,
+            hash_to_issued: HashMap::from([(genesis_hash, U256::zero())]),
+            current_longest_chain_latest_block_hash: genesis_hash,
+            difficulty_algorithm: spec.difficulty_algorithm.clone(),
+            median_time_past_window: MEDIAN_TIME_PAST_WINDOW,
+            max_future_block_time: MAX_FUTURE_BLOCK_TIME,
             hash_to_miners_who_received_the_block: HashMap::new(),
+            invalid_blocks: HashSet::new(),
+            orphans: OrphanPool::default(),
+            signature_cache: default_signature_cache(),
+            block_undo: HashMap::new(),
+            canonical_chain: vec![genesis_hash],
+            tx_index: HashMap::new(),
+        })
+    }
+
+    /// Reward the coinbase of the block at `height` must claim.
+    pub fn block_reward_at(&self, height: u64) -> U256 {
+        match height.checked_sub(1).and_then(|parent_height| self.get_hash_by_height(parent_height)) {
+            Some(parent_hash) => self.block_reward_after(parent_hash, height),
+            None => self.emission.reward_at(height),
         }
     }
 
-    pub fn get_block(&self, hash: &String) -> Option<&Block> {
+    /// Coins in existence once the block at `height` is applied: the genesis
+    /// allocations plus every block reward up to that height. Fees only move
+    /// coins, so this is also the sum of all balances at that height.
+    pub fn total_supply_at(&self, height: u64) -> U256 {
+        let issued = self
+            .get_hash_by_height(height)
+            .and_then(|hash| self.hash_to_issued.get(hash))
+            .copied()
+            .unwrap_or_else(|| self.emission.issued_through(height));
+        self.genesis_supply.saturating_add(issued)
+    }
+
+    /// Hash of the block at height 0.
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.canonical_chain[0]
+    }
+
+    pub fn get_block(&self, hash: &BlockHash) -> Option<&Block> {
         self.hash_to_block.get(hash)
     }
 
-    pub fn add_block(&mut self, mut block: Block, miner_public_key: PublicKey) -> bool {
-        // create account for miner of needed
-        if self.get_account(&miner_public_key).is_none() {
-            self.create_account(&miner_public_key);
-        }
+    /// Latest block of the canonical chain, genesis until another block
+    /// is connected.
+    pub fn tip(&self) -> &Block {
+        &self.hash_to_block[&self.current_longest_chain_latest_block_hash]
+    }
 
-        let block_merkle_root = &block.header.merkle_root;
-        let deserialized_transactions = block.get_deseralized_transactions();
-        let recomputed_merkle_root = &MerkleTree::build_tree(&deserialized_transactions)
-            .root
-            .expect("Blockchain cannot add block: Merkle root is None")
-            .value;
-        if block_merkle_root != recomputed_merkle_root {
-            return false;
-        }
+    /// Block at `height` on the canonical chain.
+    pub fn get_block_by_height(&self, height: u64) -> Option<&Block> {
+        self.get_hash_by_height(height)
+            .and_then(|hash| self.hash_to_block.get(hash))
+    }
+
+    /// Canonical blocks from height `from` to height `to`, both included,
+    /// lowest first. Heights above the tip are skipped.
+    pub fn canonical_iter(&self, from: u64, to: u64) -> impl Iterator<Item = &Block> + '_ {
+        (from..=to).map_while(move |height| self.get_block_by_height(height))
+    }
+
+    /// Block `depth` generations above `hash`, `hash` itself at depth 0.
+    pub fn ancestor(&self, hash: &BlockHash, depth: u64) -> Option<&Block> {
+        self.hash_to_block.get(&self.ancestor_hash(hash, depth)?)
+    }
 
-        block.header.difficulty = self.difficulty;
+    /// Validates `block` against its parent chain and connects it, switching
+    /// to its branch if that branch now carries the most work. A block whose
+    /// parent is unknown is kept in the orphan pool and connected, along with
+    /// any orphans waiting on it, once that parent is added.
+    pub fn add_block(&mut self, block: Block) -> Result<(), BlockError> {
         let block_hash = Block::hash_header(&block.header);
-        let block_prev_hash = &block.header.prev_hash;
-        if block.header.prev_hash != "" {
-            let block_hash_u256: Result<U256, FromStrRadixErr> =
-                U256::from_str_radix(&block_hash, 16);
-            match block_hash_u256 {
-                Ok(hash) => {
-                    // adjust difficulty
-                    if hash > self.difficulty {
-                        return false;
-                    }
-                    if self.latest_n_block_timestamps.len() as u64
-                        == self.blocks_between_difficulty_adjustment
-                    {
-                        self.adjust_difficulty();
-                    } else {
-                        self.latest_n_block_timestamps.push(block.header.timestamp);
-                    }
+        match self.connect_block(block) {
+            Ok(()) => {
+                self.connect_orphans(&block_hash);
+                Ok(())
+            }
+            Err(err) => {
+                if !matches!(err, BlockError::AlreadyKnown | BlockError::UnknownParent { .. }) {
+                    self.orphans.remove_descendants(&block_hash);
                 }
-                Err(err) => {
-                    log::error!(
-                        "Error: cannot parse block hash {}: encountered {}",
-                        &block_hash,
-                        err
-                    );
-                    return false;
+                Err(err)
+            }
+        }
+    }
+
+    /// Connects the orphans waiting on `parent_hash`, then theirs, and so on.
+    fn connect_orphans(&mut self, parent_hash: &BlockHash) {
+        let mut parent_hashes = vec![*parent_hash];
+        while let Some(parent_hash) = parent_hashes.pop() {
+            for (orphan_hash, orphan) in self.orphans.take_children(&parent_hash) {
+                match self.connect_block(orphan) {
+                    Ok(()) => parent_hashes.push(orphan_hash),
+                    Err(err) => {
+                        log::warn!("Orphan block {} was rejected: {}", orphan_hash, err);
+                        self.orphans.remove_descendants(&orphan_hash);
+                    }
                 }
             }
         }
+    }
+
+    /// Parent hashes the orphan pool is waiting for, to be requested from peers.
+    pub fn get_missing_ancestors(&self) -> Vec<BlockHash> {
+        self.orphans.missing_ancestors()
+    }
+
+    fn connect_block(&mut self, block: Block) -> Result<(), BlockError> {
+        let block_hash = Block::hash_header(&block.header);
+        if self.hash_to_block.contains_key(&block_hash) || self.orphans.contains(&block_hash) {
+            return Err(BlockError::AlreadyKnown);
+        }
+        if self.invalid_blocks.contains(&block_hash) {
+            return Err(BlockError::KnownInvalid);
+        }
+        let block_prev_hash = &block.header.prev_hash;
+        if self.invalid_blocks.contains(block_prev_hash) {
+            self.invalid_blocks.insert(block_hash);
+            return Err(BlockError::InvalidAncestor {
+                hash: *block_prev_hash,
+            });
+        }
+        if !block_prev_hash.is_genesis_parent() && !self.hash_to_block.contains_key(block_prev_hash) {
+            return self.add_orphan(block_hash, block);
+        }
+        self.validate_block(&block)?;
+
         self.hash_to_miner
-            .insert(block_hash.clone(), convert_public_key_to_bytes(&miner_public_key));
-        self.hash_to_block.insert(block_hash.clone(), block.clone());
+            .insert(block_hash, block.header.coinbase.recipient);
+        self.hash_to_block.insert(block_hash, block.clone());
         self.block_parent_map
-            .insert(block_hash.clone(), block_prev_hash.clone());
+            .insert(block_hash, *block_prev_hash);
 
         // Apply the longest chain rule
         let current_longest_chain_latest_block_hash =
-            self.current_longest_chain_latest_block_hash.clone();
-        let total_block_difficulty =
-            self.hash_to_cumulative_difficulty[&block_prev_hash.clone()] + block.header.difficulty;
-        if block_prev_hash != &current_longest_chain_latest_block_hash {
-            let current_longest_chain_latest_block_difficulty = self
-                .hash_to_cumulative_difficulty
+            self.current_longest_chain_latest_block_hash;
+        let total_block_work =
+            self.hash_to_cumulative_work[block_prev_hash] + block.header.work();
+        let connected = if block_prev_hash != &current_longest_chain_latest_block_hash {
+            let current_longest_chain_latest_block_work = self
+                .hash_to_cumulative_work
                 .get(&current_longest_chain_latest_block_hash)
                 .unwrap();
-            if &total_block_difficulty > current_longest_chain_latest_block_difficulty {
-                self.reorg_to_new_longest_chain(block_hash.clone());
-                if self.parent_block_map.contains_key(&block_prev_hash.clone()) {
-                    self.parent_block_map
-                        .insert(block_prev_hash.clone(), block_hash.clone());
-                }
+            if &total_block_work > current_longest_chain_latest_block_work {
+                self.reorg_to_new_longest_chain(block_hash).map(|()| {
+                    if self.parent_block_map.contains_key(block_prev_hash) {
+                        self.parent_block_map
+                            .insert(*block_prev_hash, block_hash);
+                    }
+                })
+            } else {
+                Ok(())
             }
         } else {
-            self.current_longest_chain_latest_block_hash = block_hash.clone();
-            self.parent_block_map
-                .insert(block_prev_hash.clone(), block_hash.clone());
-            self.apply_block_transactions(&block_hash);
+            self.apply_block_transactions(&block_hash).map(|()| {
+                self.current_longest_chain_latest_block_hash = block_hash;
+                self.canonical_chain.push(block_hash);
+                self.index_transactions(&block_hash);
+                self.parent_block_map
+                    .insert(*block_prev_hash, block_hash);
+            })
+        };
+        // A block whose transactions cannot be applied is not kept, and is
+        // remembered so that it and its descendants are rejected right away.
+        if let Err(err) = connected {
+            self.hash_to_block.remove(&block_hash);
+            self.hash_to_miner.remove(&block_hash);
+            self.block_parent_map.remove(&block_hash);
+            self.invalid_blocks.insert(block_hash);
+            return Err(err);
         }
 
-        self.hash_to_cumulative_difficulty
-            .insert(block_hash.clone(), total_block_difficulty);
-        self.cumulative_difficulty_to_hash
-            .insert(total_block_difficulty, block_hash.clone());
-        return true;
+        self.hash_to_cumulative_work
+            .insert(block_hash, total_block_work);
+        self.hash_to_issued.insert(
+            block_hash,
+            self.hash_to_issued[block_prev_hash] + block.header.coinbase.reward,
+        );
+        self.cumulative_work_to_hash
+            .insert(total_block_work, block_hash);
+        // The block's signatures are never checked again once it is kept.
+        if let (Some(cache), Ok(transactions)) = (
+            self.signature_cache.as_mut(),
+            block.get_deseralized_transactions(),
+        ) {
+            for signed_transaction in transactions.iter() {
+                cache.remove(signed_transaction, self.chain_id);
+            }
+        }
+        Ok(())
     }
 
-    pub fn set_difficulty(&mut self, new_difficulty: U256) {
-        self.difficulty = new_difficulty;
+    /// Records in the signature cache, if the chain keeps one, that the
+    /// mempool verified the signature of `signed_transaction`. Only
+    /// single-key transactions are recorded, as block validation checks
+    /// multisig signatures against the state instead.
+    pub fn cache_verified_signature(&mut self, signed_transaction: &SignedTransaction) {
+        if let (Some(cache), Sender::Key(_)) =
+            (self.signature_cache.as_mut(), &signed_transaction.transaction.from)
+        {
+            cache.insert(signed_transaction, self.chain_id);
+        }
     }
 
-    pub fn get_account(&self, public_key: &PublicKey) -> Option<&AccountState> {
-        let encoded_public_key = public_key.to_encoded_point(true);
-        let public_key_bytes = encoded_public_key.as_bytes();
-        self.accounts.get(public_key_bytes)
+    /// Keeps a block whose parent is unknown once the checks that do not need
+    /// the parent pass. The target it claims cannot be checked before the
+    /// parent arrives, so it must be no easier than both the initial target
+    /// and the tip's: otherwise the pool could be filled with blocks costing
+    /// no work. Proof of work is checked before any signature.
+    fn add_orphan(&mut self, block_hash: BlockHash, block: Block) -> Result<(), BlockError> {
+        let max_target = self.get_max_orphan_target();
+        if block.header.difficulty > max_target {
+            return Err(BlockError::OrphanTargetTooEasy {
+                max_target,
+                found: block.header.difficulty,
+            });
+        }
+        validate_proof_of_work(&block.header)?;
+        block.validate_merkle_root()?;
+        self.validate_transaction_count(&block)?;
+        block.validate_signatures(self.chain_id, self.signature_cache.as_ref())?;
+        let prev_hash = block.header.prev_hash;
+        self.orphans
+            .insert(block_hash, block, Blockchain::get_adjusted_time());
+        Err(BlockError::UnknownParent { prev_hash })
     }
 
-    pub fn get_balance(&mut self, public_key: &PublicKey) -> U256 {
-        let account = self.get_account(public_key);
-        if account.is_some() {
-            account.unwrap().balance
-        } else {
-            let new_account = self.create_account(public_key);
-            new_account.balance
+    /// Easiest target an orphan block may claim: the initial target, or the
+    /// tip's if the chain has become easier since.
+    pub fn get_max_orphan_target(&self) -> U256 {
+        self.tip().header.difficulty.max(self.initial_difficulty)
+    }
+
+    /// Runs every consensus check on `block` without modifying the chain,
+    /// cheapest first: the header checks shared with light clients, then
+    /// body commitments, transaction count and coinbase, and signatures
+    /// last, so that a block with an invalid header costs its sender's peers
+    /// no signature verification.
+    pub fn validate_block(&self, block: &Block) -> Result<(), BlockError> {
+        self.validate_header(&block.header)?;
+        block.validate_merkle_root()?;
+        self.validate_transaction_count(block)?;
+        self.validate_coinbase(block)?;
+        block.validate_signatures(self.chain_id, self.signature_cache.as_ref())
+    }
+
+    /// A block may carry at most `max_transactions_per_block` transactions.
+    fn validate_transaction_count(&self, block: &Block) -> Result<(), BlockError> {
+        if block.transactions.len() > self.max_transactions_per_block {
+            return Err(BlockError::TooManyTransactions {
+                max: self.max_transactions_per_block,
+                found: block.transactions.len(),
+            });
         }
+        Ok(())
     }
 
-    pub fn create_account(&mut self, public_key: &PublicKey) -> &AccountState {
-        let public_key_bytes = convert_public_key_to_bytes(public_key);
-        let new_account: AccountState = AccountState {
-            balance: U256::zero(),
-            nonce: 0,
-        };
-        self.accounts.insert(public_key_bytes.clone(), new_account);
-        &self.accounts[&public_key_bytes]
-    }
-
-    pub fn mint(&mut self, public_key: &PublicKey, amount: U256) {
-        let _account = self
-            .accounts
-            .get_mut(&convert_public_key_to_bytes(public_key));
-        _account.expect("Account does not exist").balance += amount;
-    }
-
-    pub fn reorg_to_new_longest_chain(&mut self, block_hash: String) {
-        let mut curr_old_chain_block_hash = self.current_longest_chain_latest_block_hash.clone();
-        let mut old_chain_block_hashes: HashSet<String> = HashSet::new();
-        let mut old_chain_block_hashes_vec = vec![];
-        while curr_old_chain_block_hash != String::from("") {
-            old_chain_block_hashes.insert(curr_old_chain_block_hash.clone());
-            old_chain_block_hashes_vec.push(curr_old_chain_block_hash.clone());
-            curr_old_chain_block_hash = self
-                .block_parent_map
-                .get(&curr_old_chain_block_hash)
-                .unwrap()
-                .clone();
+    /// The coinbase must claim exactly the emission schedule's reward for the
+    /// block height and the sum of the fees paid by the block's transactions.
+    pub fn validate_coinbase(&self, block: &Block) -> Result<(), BlockError> {
+        let coinbase = &block.header.coinbase;
+        if coinbase.reward != self.block_reward_after(&block.header.prev_hash, block.header.height)
+            || coinbase.fees != Block::total_fees(&block.get_deseralized_transactions()?)
+        {
+            return Err(BlockError::BadCoinbase);
         }
-        let mut curr_new_chain_block_hash = block_hash.clone();
-        let mut new_chain_block_hashes: HashSet<String> = HashSet::new();
-        let mut new_chain_block_hashes_vec = vec![];
-        while curr_new_chain_block_hash != String::from("") {
-            new_chain_block_hashes.insert(curr_new_chain_block_hash.clone());
-            new_chain_block_hashes_vec.push(curr_new_chain_block_hash.clone());
-            curr_new_chain_block_hash = self
-                .block_parent_map
-                .get(&curr_new_chain_block_hash)
-                .unwrap()
-                .clone();
+        Ok(())
+    }
+
+    /// Local clock in seconds since the Unix epoch.
+    pub fn get_adjusted_time() -> u64 {
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => n.as_secs(),
+            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
         }
+    }
 
-        let fork_hash = old_chain_block_hashes
-            .intersection(&new_chain_block_hashes)
-            .next()
-            .expect("No fork hash found");
+    pub fn get_account(&self, address: &Address) -> Option<&AccountState> {
+        self.accounts.get(address)
+    }
 
-        let fork_hash_idx_in_old_block_vec = old_chain_block_hashes_vec
-            .iter()
-            .position(|h| h == fork_hash)
-            .unwrap();
+    pub fn get_balance(&mut self, address: &Address) -> U256 {
+        match self.get_account(address) {
+            Some(account) => account.balance,
+            None => self.create_account(address).balance,
+        }
+    }
+
+    pub fn create_account(&mut self, address: &Address) -> &AccountState {
+        self.accounts.insert(*address, AccountState::default());
+        &self.accounts[address]
+    }
+
+    /// Bech32 text of `address` on this chain.
+    pub fn format_address(&self, address: &Address) -> String {
+        address.to_bech32(&self.address_prefix)
+    }
+
+    /// Reads a bech32 address, rejecting addresses of other networks.
+    pub fn parse_address(&self, text: &str) -> Result<Address, AddressError> {
+        Address::from_bech32(text, &self.address_prefix)
+    }
 
-        let old_chain_block_hashes_vec_slice =
-            &old_chain_block_hashes_vec[..fork_hash_idx_in_old_block_vec + 1];
+    /// Switches the canonical chain to the branch ending at `block_hash`.
+    /// Only the blocks above the fork point are touched: the old ones are
+    /// rolled back from their undo records and the new ones applied, so the
+    /// cost grows with the depth of the reorg rather than the chain length.
+    pub fn reorg_to_new_longest_chain(&mut self, block_hash: BlockHash) -> Result<(), BlockError> {
+        let (old_chain_block_hashes, mut new_chain_block_hashes) =
+            self.get_fork_branches(&self.current_longest_chain_latest_block_hash, &block_hash);
 
-        for old_chain_block_hash in old_chain_block_hashes_vec_slice.iter() {
-            self.revert_block_transactions(&old_chain_block_hash);
+        // The whole switch is staged so that a block failing to apply leaves
+        // the accounts and the tip exactly as they were.
+        let mut state = StateOverlay::new(&self.accounts);
+        for old_chain_block_hash in old_chain_block_hashes.iter() {
+            let undo = self
+                .block_undo
+                .get(old_chain_block_hash)
+                .expect("Canonical block to have an undo record");
+            state.restore(undo);
         }
 
-        new_chain_block_hashes_vec.reverse();
-        let fork_hash_idx_in_new_block_vec = new_chain_block_hashes_vec
-            .iter()
-            .position(|h| h == fork_hash)
-            .unwrap();
-        let new_chain_block_hashes_vec_slice =
-            &new_chain_block_hashes_vec[fork_hash_idx_in_new_block_vec..];
-        for new_chain_block_hash in new_chain_block_hashes_vec_slice.iter() {
-            self.apply_block_transactions(&new_chain_block_hash);
+        new_chain_block_hashes.reverse();
+        let mut new_chain_block_undo = Vec::with_capacity(new_chain_block_hashes.len());
+        for new_chain_block_hash in new_chain_block_hashes.iter() {
+            if self.invalid_blocks.contains(new_chain_block_hash) {
+                return Err(BlockError::InvalidAncestor {
+                    hash: *new_chain_block_hash,
+                });
+            }
+            let new_chain_block = &self.hash_to_block[new_chain_block_hash];
+            state.open_journal();
+            let applied = Blockchain::apply_block_to_state(&mut state, new_chain_block, self.chain_id)
+                .and_then(|()| {
+                    Blockchain::validate_state_root(&self.state_tree, &state, &new_chain_block.header)
+                });
+            if let Err(err) = applied {
+                self.invalid_blocks.insert(*new_chain_block_hash);
+                return Err(err);
+            }
+            new_chain_block_undo.push((*new_chain_block_hash, state.close_journal()));
+        }
+        let changes = state.into_changes();
+        self.commit_state_changes(changes);
+        for old_chain_block_hash in old_chain_block_hashes.iter() {
+            self.block_undo.remove(old_chain_block_hash);
+            self.unindex_transactions(old_chain_block_hash);
+        }
+        self.block_undo.extend(new_chain_block_undo);
+        for new_chain_block_hash in new_chain_block_hashes.iter() {
+            self.index_transactions(new_chain_block_hash);
+        }
+        self.canonical_chain
+            .truncate(self.canonical_chain.len() - old_chain_block_hashes.len());
+        self.canonical_chain.extend(new_chain_block_hashes);
+        self.current_longest_chain_latest_block_hash = block_hash;
+        Ok(())
+    }
+
+    /// Blocks of each branch above the last block `old_tip` and `new_tip`
+    /// have in common, tip first. Walks back from whichever side is higher,
+    /// so only the blocks above the fork point are visited.
+    pub fn get_fork_branches(&self, old_tip: &BlockHash, new_tip: &BlockHash) -> (Vec<BlockHash>, Vec<BlockHash>) {
+        let height_of = |hash: &BlockHash| {
+            self.hash_to_block
+                .get(hash)
+                .map(|block| block.header.height)
+        };
+        let mut old_chain_block_hashes = vec![];
+        let mut new_chain_block_hashes = vec![];
+        let mut old_hash = *old_tip;
+        let mut new_hash = *new_tip;
+        while old_hash != new_hash {
+            // The genesis marker has no height and sorts below every block.
+            if height_of(&old_hash) >= height_of(&new_hash) {
+                let parent_hash = self.block_parent_map[&old_hash];
+                old_chain_block_hashes.push(std::mem::replace(&mut old_hash, parent_hash));
+            } else {
+                let parent_hash = self.block_parent_map[&new_hash];
+                new_chain_block_hashes.push(std::mem::replace(&mut new_hash, parent_hash));
+            }
         }
+        (old_chain_block_hashes, new_chain_block_hashes)
     }
 
-    fn apply_block_transactions(&mut self, block_hash: &str) -> bool {
+    /// Applies a block to the committed accounts, all or nothing: every
+    /// transaction is checked against a staged copy of the accounts it
+    /// touches, and the copy only replaces them once the whole block applied.
+    /// The prior state of those accounts is kept as the block's undo record.
+    fn apply_block_transactions(&mut self, block_hash: &BlockHash) -> Result<(), BlockError> {
         let block = self
             .hash_to_block
             .get(block_hash)
             .expect("Block does not exist.");
-        let miner_public_key = self.hash_to_miner.get(block_hash).unwrap();
-        let mut miner_account_balance = self
-            .accounts
-            .get(miner_public_key)
-            .unwrap()
-            .balance;
-        let deserialized_transactions = block.get_deseralized_transactions();
-        for transaction in deserialized_transactions.iter() {
-            let sender_public_key = &transaction.public_key_from;
-            let sender_account = self
-                .accounts
-                .get_mut(&convert_public_key_to_bytes(sender_public_key));
-            if sender_account.is_none() {
-                return false;
+        let mut state = StateOverlay::new(&self.accounts);
+        state.open_journal();
+        Blockchain::apply_block_to_state(&mut state, block, self.chain_id)?;
+        Blockchain::validate_state_root(&self.state_tree, &state, &block.header)?;
+        let undo = state.close_journal();
+        let changes = state.into_changes();
+        self.commit_state_changes(changes);
+        self.block_undo.insert(*block_hash, undo);
+        Ok(())
+    }
+
+    /// The header must commit to the accounts as `state` leaves them once
+    /// the block is applied, `state_tree` holding the committed accounts.
+    fn validate_state_root(
+        state_tree: &StateTree,
+        state: &StateOverlay,
+        header: &Header,
+    ) -> Result<(), BlockError> {
+        let state_root = state_tree.root_with(state.changes());
+        if header.state_root != state_root {
+            return Err(BlockError::BadStateRoot {
+                expected: state_root,
+                found: header.state_root,
+            });
+        }
+        Ok(())
+    }
+
+    /// Root of the accounts once `block` is applied on top of its parent,
+    /// which need not be on the canonical chain. This is the `state_root`
+    /// a producer puts in the header.
+    pub fn compute_state_root(&self, block: &Block) -> Result<StateHash, BlockError> {
+        let prev_hash = &block.header.prev_hash;
+        if !self.hash_to_block.contains_key(prev_hash) {
+            return Err(BlockError::UnknownParent {
+                prev_hash: *prev_hash,
+            });
+        }
+        let mut state = self.stage_state_at(prev_hash)?;
+        Blockchain::apply_block_to_state(&mut state, block, self.chain_id)?;
+        Ok(self.state_tree.root_with(state.changes()))
+    }
+
+    /// Stages the accounts as they are right after `block_hash`: canonical
+    /// blocks above its fork point are undone from their records and the
+    /// blocks of its branch applied on top.
+    fn stage_state_at(&self, block_hash: &BlockHash) -> Result<StateOverlay<'_>, BlockError> {
+        let (old_chain_block_hashes, mut new_chain_block_hashes) =
+            self.get_fork_branches(&self.current_longest_chain_latest_block_hash, block_hash);
+        let mut state = StateOverlay::new(&self.accounts);
+        for old_chain_block_hash in old_chain_block_hashes.iter() {
+            state.restore(&self.block_undo[old_chain_block_hash]);
+        }
+        new_chain_block_hashes.reverse();
+        for new_chain_block_hash in new_chain_block_hashes.iter() {
+            if self.invalid_blocks.contains(new_chain_block_hash) {
+                return Err(BlockError::InvalidAncestor {
+                    hash: *new_chain_block_hash,
+                });
             }
-            let sender_account_state = sender_account.expect("Sender account does not exist");
+            Blockchain::apply_block_to_state(
+                &mut state,
+                &self.hash_to_block[new_chain_block_hash],
+                self.chain_id,
+            )?;
+        }
+        Ok(state)
+    }
+
+    /// Proof of the state of the account at `address` at the tip, to be
+    /// checked against the tip header's `state_root`.
+    pub fn prove_account(&self, address: &Address) -> AccountProof {
+        self.state_tree.prove(address)
+    }
+
+    /// Looks up the canonical block holding the transaction `tx_hash` in
+    /// `tx_index` and proves its inclusion for light clients, which check it
+    /// against that block's `merkle_root`. Only that block is decoded.
+    pub fn prove_transaction(
+        &self,
+        tx_hash: &TxHash,
+    ) -> Option<(BlockHash, SignedTransaction, MerkleProof)> {
+        let (block_hash, index) = self.tx_index.get(tx_hash)?;
+        let transactions = self.hash_to_block[block_hash]
+            .get_deseralized_transactions()
+            .ok()?;
+        let proof = MerkleTree::build_tree(&transactions).prove(*index)?;
+        Some((*block_hash, transactions[*index].clone(), proof))
+    }
+
+    /// Adds the transactions of the block `block_hash`, which just joined
+    /// the canonical chain, to `tx_index`.
+    fn index_transactions(&mut self, block_hash: &BlockHash) {
+        let Ok(transactions) = self.hash_to_block[block_hash].get_deseralized_transactions() else {
+            return;
+        };
+        for (index, signed_transaction) in transactions.iter().enumerate() {
+            self.tx_index.insert(
+                hash_transaction(&signed_transaction.transaction, self.chain_id),
+                (*block_hash, index),
+            );
+        }
+    }
+
+    /// Removes the transactions of the block `block_hash`, which just left
+    /// the canonical chain, from `tx_index`.
+    fn unindex_transactions(&mut self, block_hash: &BlockHash) {
+        let Ok(transactions) = self.hash_to_block[block_hash].get_deseralized_transactions() else {
+            return;
+        };
+        for signed_transaction in transactions.iter() {
+            self.tx_index
+                .remove(&hash_transaction(&signed_transaction.transaction, self.chain_id));
+        }
+    }
+
+    fn commit_state_changes(&mut self, changes: HashMap<Address, Option<AccountState>>) {
+        self.state_tree.update(&changes);
+        for (address, account) in changes {
+            match account {
+                Some(account) => {
+                    self.accounts.insert(address, account);
+                }
+                None => {
+                    self.accounts.remove(&address);
+                }
+            }
+        }
+    }
+
+    /// Applies the transactions and the coinbase of `block`. Single-key
+    /// signatures are checked beforehand by `validate_signatures`; multisig
+    /// ones are checked here, against the policy their account has
+    /// registered at that point of the block.
+    fn apply_block_to_state(
+        state: &mut StateOverlay,
+        block: &Block,
+        chain_id: u64,
+    ) -> Result<(), BlockError> {
+        let coinbase = &block.header.coinbase;
+        let deserialized_transactions = block.get_deseralized_transactions()?;
+        for (index, signed_transaction) in deserialized_transactions.iter().enumerate() {
+            let transaction = &signed_transaction.transaction;
+            let invalid_transaction = |error| BlockError::InvalidTransaction { index, error };
+            if let Sender::Multisig(address) = &transaction.from {
+                let multisig = state.get(address).and_then(|account| account.multisig.as_ref());
+                signed_transaction
+                    .verify_signatures(chain_id, multisig)
+                    .map_err(invalid_transaction)?;
+            }
+            let recipient = transaction.recipient();
+            transaction
+                .validate_recipient(state.get(&recipient).and_then(|account| account.multisig.as_ref()))
+                .map_err(invalid_transaction)?;
+            let sender_account_state = state
+                .get_mut(&transaction.sender())
+                .ok_or(invalid_transaction(TransactionError::UnknownAccount))?;
             if transaction.nonce != sender_account_state.nonce {
-                return false;
+                return Err(invalid_transaction(TransactionError::BadNonce {
+                    expected: sender_account_state.nonce,
+                    found: transaction.nonce,
+                }));
             }
-            if sender_account_state.balance < transaction.amount + transaction.fee {
-                return false;
+            let required = transaction.amount.saturating_add(transaction.fee);
+            if sender_account_state.balance < required {
+                return Err(invalid_transaction(TransactionError::InsufficientBalance {
+                    balance: sender_account_state.balance,
+                    required,
+                }));
             }
-            sender_account_state.balance -= transaction.amount + transaction.fee;
+            sender_account_state.balance -= required;
             sender_account_state.nonce += 1;
 
-            let receiver_public_key = &transaction.public_key_to;
-            let receiver_account = self
-                .accounts
-                .get_mut(&convert_public_key_to_bytes(receiver_public_key));
-            let receiver_account_state = receiver_account.expect("Receiver account does not exist");
-            receiver_account_state.balance += transaction.amount;
-            miner_account_balance += transaction.fee;
+            let recipient_account_state = state.get_or_create_mut(&recipient);
+            recipient_account_state.balance += transaction.amount;
+            if let Recipient::NewMultisig(policy) = &transaction.to {
+                recipient_account_state.multisig = Some(policy.clone());
+            }
         }
-        let miner_account = self
-            .accounts
-            .get_mut(miner_public_key)
-            .unwrap();
-        miner_account.balance = miner_account_balance + self.mining_reward;
-        return true;
+        state
+            .get_or_create_mut(&coinbase.recipient)
+            .balance += coinbase.reward + coinbase.fees;
+        Ok(())
     }
+}
 
-    fn revert_block_transactions(&mut self, block_hash: &str) -> bool {
-        let block = self
-            .hash_to_block
-            .get(block_hash)
-            .expect("Block does not exist.");
-        
-        let miner_public_key = self.hash_to_miner.get(block_hash).unwrap();
-
-        let mut miner_account_balance = self
-            .accounts
-            .get(miner_public_key)
-            .unwrap()
-            .balance;
-
-        let deserialized_transactions = block.get_deseralized_transactions();
-
-        for transaction in deserialized_transactions.iter() {
-            let sender_public_key = &transaction.public_key_from;
-            let sender_account = self
-                .accounts
-                .get_mut(&convert_public_key_to_bytes(sender_public_key));
-            let sender_account_state = sender_account.expect("Sender account does not exist");
-            sender_account_state.balance += transaction.amount + transaction.fee;
-            sender_account_state.nonce -= 1;
-            let receiver_public_key = &transaction.public_key_to;
-            let receiver_account = self
-                .accounts
-                .get_mut(&convert_public_key_to_bytes(receiver_public_key));
-            let receiver_account_state = receiver_account.expect("Receiver account does not exist");
-            receiver_account_state.balance -= transaction.amount;
-            miner_account_balance -= transaction.fee;
-        }
+impl HeaderChain for Blockchain {
+    fn get_header(&self, hash: &BlockHash) -> Option<&Header> {
+        self.hash_to_block.get(hash).map(|block| &block.header)
+    }
 
-        let miner_account = self
-            .accounts
-            .get_mut(miner_public_key)
-            .unwrap();
-        miner_account.balance = miner_account_balance - self.mining_reward;
-        return true;
+    fn get_hash_by_height(&self, height: u64) -> Option<&BlockHash> {
+        self.canonical_chain.get(usize::try_from(height).ok()?)
     }
 
-    fn adjust_difficulty(&mut self) {
-        let difficulty_variation = self.difficulty * 2 / 100;
-        let mut total_latest_blocks_production_time: u64 = 0;
-        for i in 0..self.latest_n_block_timestamps.len() - 1 {
-            total_latest_blocks_production_time +=
-                self.latest_n_block_timestamps[i + 1] - self.latest_n_block_timestamps[i];
-        }
-        let average_production_time =
-            total_latest_blocks_production_time / (self.blocks_between_difficulty_adjustment - 1);
-        if average_production_time < self.target_duration_between_blocks * 95 / 100 && self.difficulty < U256::MAX - difficulty_variation {
-            self.difficulty += difficulty_variation;
-        } else if average_production_time > self.target_duration_between_blocks * 105 / 100 {
-            self.difficulty -= difficulty_variation;
+    fn get_issued(&self, hash: &BlockHash) -> Option<U256> {
+        self.hash_to_issued.get(hash).copied()
+    }
+
+    fn header_rules(&self) -> HeaderRules<'_> {
+        HeaderRules {
+            initial_difficulty: self.initial_difficulty,
+            difficulty_algorithm: &self.difficulty_algorithm,
+            target_duration_between_blocks: self.target_duration_between_blocks,
+            emission: &self.emission,
+            median_time_past_window: self.median_time_past_window,
+            max_future_block_time: self.max_future_block_time,
         }
     }
 }
@@ -1,5 +1,6 @@
 pub use crate::blockchain::{
     self,
+    address::Address,
     block::{self, Block, Header, Transaction},
     utils::hash_transaction,
     Blockchain,
@@ -14,13 +15,19 @@ pub struct AccountKeys {
     private_key: SigningKey,
 }
 
+impl Default for AccountKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AccountKeys {
     pub fn get_balance(&self, blockchain: &mut Blockchain) -> U256 {
-        blockchain.get_balance(&self.public_key)
+        blockchain.get_balance(&self.get_address())
     }
 
-    pub fn sign_transaction(&mut self, transaction: &Transaction) -> Signature {
-        let transaction_hash = hash_transaction(transaction);
+    pub fn sign_transaction(&mut self, transaction: &Transaction, chain_id: u64) -> Signature {
+        let transaction_hash = hash_transaction(transaction, chain_id);
         self.private_key.sign(transaction_hash.as_bytes())
     }
 
@@ -34,7 +41,20 @@ impl AccountKeys {
         }
     }
 
+    pub fn from_signing_key(private_key: SigningKey) -> Self {
+        let verifying_key = VerifyingKey::from(&private_key);
+        let public_key = verifying_key.into();
+        Self {
+            private_key,
+            public_key,
+        }
+    }
+
     pub fn get_public_key(&self) -> PublicKey {
         self.public_key
     }
+
+    pub fn get_address(&self) -> Address {
+        Address::from_public_key(&self.public_key)
+    }
 }
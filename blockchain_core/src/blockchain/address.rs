@@ -0,0 +1,216 @@
+use super::utils::convert_public_key_to_bytes;
+use k256::PublicKey;
+use rustc_hex::{FromHex, ToHex};
+use serde::{Deserialize, Serialize};
+use sha256::digest;
+use std::fmt;
+
+/// Number of bytes in an address.
+pub const ADDRESS_LENGTH: usize = 20;
+/// Longest text address bech32 allows, prefix and checksum included.
+const MAX_BECH32_LENGTH: usize = 90;
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+/// Constant the checksum is xored with in bech32m (BIP-350).
+const BECH32M_CONSTANT: u32 = 0x2bc830a3;
+const CHECKSUM_LENGTH: usize = 6;
+
+/// Why a string could not be read as an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidLength(usize),
+    MixedCase,
+    MissingSeparator,
+    WrongPrefix { expected: String, found: String },
+    InvalidCharacter(char),
+    InvalidChecksum,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidLength(length) => write!(
+                f,
+                "address is {} bytes long, expected {}",
+                length, ADDRESS_LENGTH
+            ),
+            AddressError::MixedCase => write!(f, "address mixes upper and lower case"),
+            AddressError::MissingSeparator => write!(f, "address has no prefix separator"),
+            AddressError::WrongPrefix { expected, found } => write!(
+                f,
+                "address prefix {} does not match the network prefix {}",
+                found, expected
+            ),
+            AddressError::InvalidCharacter(character) => {
+                write!(f, "address contains invalid character {:?}", character)
+            }
+            AddressError::InvalidChecksum => write!(f, "address checksum is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Identifies an account: the first 20 bytes of the SHA-256 of the
+/// account's compressed public key, so coins can be sent to an account
+/// without knowing its key. Addresses are only ever written as bech32m
+/// text (`to_bech32`), whose prefix names the network and whose checksum
+/// catches typos. Since the text depends on the network, there is no
+/// `Display` or `FromStr`: nodes go through `Blockchain::format_address`
+/// and `parse_address`, and chain specs through their `address_prefix`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct Address([u8; ADDRESS_LENGTH]);
+
+impl Address {
+    pub fn from_public_key(public_key: &PublicKey) -> Self {
+        Self::from_preimage(&convert_public_key_to_bytes(public_key))
+    }
+
+    /// First 20 bytes of the SHA-256 of `preimage`.
+    pub(crate) fn from_preimage(preimage: &[u8]) -> Self {
+        let preimage_hash: Vec<u8> = digest(preimage)
+            .from_hex()
+            .expect("SHA-256 digest to be hex");
+        let mut bytes = [0; ADDRESS_LENGTH];
+        bytes.copy_from_slice(&preimage_hash[..ADDRESS_LENGTH]);
+        Self(bytes)
+    }
+
+    pub const fn from_bytes(bytes: [u8; ADDRESS_LENGTH]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; ADDRESS_LENGTH] {
+        &self.0
+    }
+
+    /// Bech32m text of the address on the network identified by `prefix`,
+    /// such as `bp1...`.
+    pub fn to_bech32(&self, prefix: &str) -> String {
+        let mut data = convert_bits(&self.0, 8, 5, true).expect("Bytes to fit in 5-bit groups");
+        data.extend(bech32m_checksum(prefix, &data));
+        let mut text = String::with_capacity(prefix.len() + 1 + data.len());
+        text.push_str(prefix);
+        text.push('1');
+        text.extend(data.iter().map(|value| BECH32_CHARSET[*value as usize] as char));
+        text
+    }
+
+    /// Reads bech32m `text`, which must be entirely lower or upper case and
+    /// carry the network `prefix`.
+    pub fn from_bech32(text: &str, prefix: &str) -> Result<Self, AddressError> {
+        if text.chars().any(|c| c.is_ascii_lowercase()) && text.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(AddressError::MixedCase);
+        }
+        let text = text.to_ascii_lowercase();
+        let separator = text.rfind('1').ok_or(AddressError::MissingSeparator)?;
+        let (found_prefix, data) = (&text[..separator], &text[separator + 1..]);
+        if found_prefix != prefix.to_ascii_lowercase() {
+            return Err(AddressError::WrongPrefix {
+                expected: prefix.to_string(),
+                found: found_prefix.to_string(),
+            });
+        }
+        if text.len() > MAX_BECH32_LENGTH || data.len() < CHECKSUM_LENGTH {
+            return Err(AddressError::InvalidChecksum);
+        }
+        let data: Vec<u8> = data
+            .chars()
+            .map(|character| {
+                BECH32_CHARSET
+                    .iter()
+                    .position(|charset_character| *charset_character as char == character)
+                    .map(|value| value as u8)
+                    .ok_or(AddressError::InvalidCharacter(character))
+            })
+            .collect::<Result<_, _>>()?;
+        let mut values = expand_prefix(found_prefix);
+        values.extend(&data);
+        if polymod(&values) != BECH32M_CONSTANT {
+            return Err(AddressError::InvalidChecksum);
+        }
+        let payload = &data[..data.len() - CHECKSUM_LENGTH];
+        let bytes = convert_bits(payload, 5, 8, false).ok_or(AddressError::InvalidChecksum)?;
+        let bytes: [u8; ADDRESS_LENGTH] = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| AddressError::InvalidLength(bytes.len()))?;
+        Ok(Self(bytes))
+    }
+
+    /// Whether `prefix` can start a bech32 address: 1 to 83 printable
+    /// ASCII characters, all lower case.
+    pub fn is_valid_prefix(prefix: &str) -> bool {
+        (1..=83).contains(&prefix.len())
+            && prefix
+                .bytes()
+                .all(|byte| (33..=126).contains(&byte) && !byte.is_ascii_uppercase())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address({})", self.0.to_hex::<String>())
+    }
+}
+
+/// BCH checksum of the bech32 family over 5-bit values.
+fn polymod(values: &[u8]) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ u32::from(*value);
+        for (bit, generator) in BECH32_GENERATOR.iter().enumerate() {
+            if (top >> bit) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// The prefix as checksummed: high bits of each character, a zero, then
+/// the low bits.
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = prefix.bytes().map(|byte| byte >> 5).collect();
+    values.push(0);
+    values.extend(prefix.bytes().map(|byte| byte & 31));
+    values
+}
+
+fn bech32m_checksum(prefix: &str, data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let mut values = expand_prefix(prefix);
+    values.extend(data);
+    values.extend([0; CHECKSUM_LENGTH]);
+    let checksum = polymod(&values) ^ BECH32M_CONSTANT;
+    let mut checksum_values = [0; CHECKSUM_LENGTH];
+    for (index, value) in checksum_values.iter_mut().enumerate() {
+        *value = ((checksum >> (5 * (CHECKSUM_LENGTH - 1 - index))) & 31) as u8;
+    }
+    checksum_values
+}
+
+/// Regroups `data` from `from_bits` to `to_bits` bits per value. Without
+/// `pad`, leftover bits must be fewer than `from_bits` and all zero.
+fn convert_bits(data: &[u8], from_bits: u32, to_bits: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator: u32 = 0;
+    let mut bits: u32 = 0;
+    let max_value = (1 << to_bits) - 1;
+    let max_accumulator = (1 << (from_bits + to_bits - 1)) - 1;
+    let mut converted = Vec::new();
+    for value in data {
+        accumulator = ((accumulator << from_bits) | u32::from(*value)) & max_accumulator;
+        bits += from_bits;
+        while bits >= to_bits {
+            bits -= to_bits;
+            converted.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((accumulator << (to_bits - bits)) & max_value) as u8);
+        }
+    } else if bits >= from_bits || (accumulator << (to_bits - bits)) & max_value != 0 {
+        return None;
+    }
+    Some(converted)
+}
@@ -1,18 +1,66 @@
-use super::utils::convert_transaction_to_string;
-use k256::{PublicKey};
+use super::address::Address;
+use super::encoding::{Decode, DecodeError, Encode};
+use super::error::{BlockError, TransactionError};
+use super::hash::{BlockHash, MerkleHash, StateHash};
+use super::multisig::MultisigPolicy;
+use super::signature_cache::SignatureCache;
+use super::utils::hash_transaction;
+use k256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    PublicKey,
+};
 use primitive_types::U256;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use sha256::digest;
-use serde_json;
 
 
+/// Current header layout. Bumped whenever the hashed preimage changes.
+pub const BLOCK_VERSION: u32 = 6;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Header {
+    pub version: u32,
+    pub height: u64,
     pub nonce: u64,
     pub timestamp: u64,
-    pub prev_hash: String,
+    pub prev_hash: BlockHash,
     pub difficulty: U256,
-    pub merkle_root: String,
+    /// Root of the Merkle tree over the transactions, all zeros for an
+    /// empty body.
+    pub merkle_root: MerkleHash,
+    /// Root of the account state tree once this block is applied.
+    pub state_root: StateHash,
+    pub coinbase: Coinbase,
+}
+
+impl Header {
+    /// Proof-of-work check shared by the miner and the validator: the
+    /// header hash, read as a big-endian integer, must not exceed the target.
+    pub fn meets_target(&self) -> bool {
+        Block::hash_header(self).to_u256() <= self.difficulty
+    }
+
+    /// Expected number of hashes needed to meet this header's target,
+    /// `2^256 / (target + 1)`. Lower targets are harder and weigh more.
+    pub fn work(&self) -> U256 {
+        let target = self.difficulty;
+        if target == U256::MAX {
+            return U256::one();
+        }
+        // 2^256 does not fit in a U256, but 2^256 / (target + 1) equals
+        // (2^256 - target - 1) / (target + 1) + 1 = !target / (target + 1) + 1.
+        (!target / (target + 1)).saturating_add(U256::one())
+    }
+}
+
+/// Block producer payout, committed to by the header hash. Every node
+/// credits `recipient` with `reward + fees` when applying the block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Coinbase {
+    pub recipient: Address,
+    pub reward: U256,
+    pub fees: U256,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,53 +69,183 @@ pub struct Block {
     pub transactions: Vec<Vec<u8>>,
 }
 
+/// A transfer of `amount` from the account `from` to the account `to`.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
-    pub public_key_from: PublicKey,
-    pub public_key_to: PublicKey,
+    pub from: Sender,
+    pub to: Recipient,
     pub amount: U256,
     pub fee: U256,
     pub nonce: u128,
 }
 
+/// Account a transaction spends from, and how its signatures are checked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Sender {
+    /// A single-key account. The key is supplied with the transaction, it
+    /// must have made the one signature and the account is its address.
+    Key(PublicKey),
+    /// A registered multisig account, whose signatures are checked against
+    /// the policy recorded in its account state.
+    Multisig(Address),
+}
+
+/// Account a transaction pays.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Recipient {
+    Account(Address),
+    /// Registers a multisig account controlled by the policy, at the
+    /// policy's address, and pays it. An address is registered only once.
+    NewMultisig(MultisigPolicy),
+}
+
+/// A transaction together with the sender's signatures over its hash.
+/// This is the unit stored in blocks, so every node can check the
+/// signatures again instead of trusting the producer's mempool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedTransaction {
+    pub transaction: Transaction,
+    pub signatures: Vec<Signature>,
+}
+
 impl Transaction {
+    /// Address of the account the transaction spends from.
+    pub fn sender(&self) -> Address {
+        match &self.from {
+            Sender::Key(public_key) => Address::from_public_key(public_key),
+            Sender::Multisig(address) => *address,
+        }
+    }
+
+    /// Address of the account the transaction pays.
+    pub fn recipient(&self) -> Address {
+        match &self.to {
+            Recipient::Account(address) => *address,
+            Recipient::NewMultisig(policy) => policy.address(),
+        }
+    }
+
+    /// Checks what a transaction's recipient requires of the state: a
+    /// multisig registration needs a valid policy and an address that is
+    /// not registered yet. `registered` is the policy already recorded at
+    /// the recipient address, if any.
+    pub fn validate_recipient(
+        &self,
+        registered: Option<&MultisigPolicy>,
+    ) -> Result<(), TransactionError> {
+        if let Recipient::NewMultisig(policy) = &self.to {
+            policy.validate()?;
+            if registered.is_some() {
+                return Err(TransactionError::AlreadyRegistered);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    pub fn deseralize(serialized_tx: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(serialized_tx)
+    }
+}
+
+impl SignedTransaction {
+    pub fn new(transaction: Transaction, signature: Signature) -> Self {
+        Self::with_signatures(transaction, vec![signature])
+    }
+
+    /// Multisig transactions carry one signature per required key.
+    pub fn with_signatures(transaction: Transaction, signatures: Vec<Signature>) -> Self {
+        Self {
+            transaction,
+            signatures,
+        }
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
-        serde_json::to_vec(self).expect("Transaction to be serialized")
+        self.encode()
+    }
+
+    pub fn deseralize(serialized_tx: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(serialized_tx)
+    }
+
+    /// Checks that a single-key sender made the transaction's one signature
+    /// for this exact transaction on the chain identified by `chain_id`.
+    /// Always false for multisig senders, see `verify_signatures`.
+    pub fn verify_signature(&self, chain_id: u64) -> bool {
+        let (Sender::Key(public_key), [signature]) = (&self.transaction.from, &self.signatures[..])
+        else {
+            return false;
+        };
+        VerifyingKey::from(public_key)
+            .verify(hash_transaction(&self.transaction, chain_id).as_bytes(), signature)
+            .is_ok()
+    }
+
+    /// Checks the signatures of any sender. A multisig sender's signatures
+    /// are checked against `multisig`, the policy registered for its
+    /// account, which a single-key sender does not need.
+    pub fn verify_signatures(
+        &self,
+        chain_id: u64,
+        multisig: Option<&MultisigPolicy>,
+    ) -> Result<(), TransactionError> {
+        let valid = match &self.transaction.from {
+            Sender::Key(_) => self.verify_signature(chain_id),
+            Sender::Multisig(_) => multisig
+                .ok_or(TransactionError::NotMultisig)?
+                .verify(&hash_transaction(&self.transaction, chain_id), &self.signatures),
+        };
+        if !valid {
+            return Err(TransactionError::BadSignature);
+        }
+        Ok(())
     }
+}
 
-    pub fn deseralize(serialized_tx: &[u8]) -> Self {
-        serde_json::from_slice(serialized_tx).expect("Transaction to be deserialized")
+impl Coinbase {
+    pub fn new(recipient: Address, reward: U256, transactions: &[SignedTransaction]) -> Self {
+        Self {
+            recipient,
+            reward,
+            fees: Block::total_fees(transactions),
+        }
     }
 }
 
 impl Block {
+    /// Builds a block over `transactions`. Its `state_root` is left empty for
+    /// the caller to fill in once the block's effect on the accounts is known.
     pub fn create_block(
         nonce: u64,
         timestamp: u64,
-        prev_hash: String,
-        transactions: &Vec<Transaction>,
+        prev_hash: BlockHash,
+        height: u64,
+        difficulty: U256,
+        transactions: &[SignedTransaction],
+        coinbase: Coinbase,
     ) -> Self {
         let merkle_tree = MerkleTree::build_tree(transactions);
 
-        let mut block_merkle_root = String::from("");
-
-        if let Some(ref root) = merkle_tree.root {
-            let root_hash = &root.value;
-            block_merkle_root.push_str(root_hash);
-        }
-
         let header = Header {
+            version: BLOCK_VERSION,
+            height,
             nonce,
             timestamp,
             prev_hash,
-            difficulty: U256::zero(),
-            merkle_root: block_merkle_root,
+            difficulty,
+            merkle_root: merkle_tree.get_root().unwrap_or_default(),
+            state_root: StateHash::default(),
+            coinbase,
         };
 
-        let mut serialized_transactions: Vec<Vec<u8>>  = vec![];
-        for transaction in transactions.iter() {
-            serialized_transactions.push(transaction.serialize());
-        }
+        let serialized_transactions: Vec<Vec<u8>> = transactions
+            .iter()
+            .map(|transaction| transaction.serialize())
+            .collect();
 
         Self {
             header,
@@ -75,23 +253,102 @@ impl Block {
         }
     }
 
-    pub fn hash_header(header: &Header) -> String {
-        let mut hash_string = String::from("");
+    /// Hashes the canonical encoding of the header, which covers every
+    /// field and is prefix-free, so no two headers share a preimage.
+    pub fn hash_header(header: &Header) -> BlockHash {
+        BlockHash::digest(&header.encode())
+    }
 
-        hash_string.push_str(&header.nonce.to_string());
-        hash_string.push_str(&header.timestamp.to_string());
-        hash_string.push_str(&header.prev_hash);
-        hash_string.push_str(&header.merkle_root);
+    /// Decodes the block body, reporting the index of the first transaction
+    /// that is not a valid encoding.
+    pub fn get_deseralized_transactions(&self) -> Result<Vec<SignedTransaction>, BlockError> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .map(|(index, transaction)| {
+                SignedTransaction::deseralize(transaction).map_err(|err| {
+                    BlockError::InvalidTransaction {
+                        index,
+                        error: err.into(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Sum of the transaction fees, saturating so that a block claiming
+    /// absurd fees is rejected instead of overflowing.
+    pub fn total_fees(transactions: &[SignedTransaction]) -> U256 {
+        transactions.iter().fold(U256::zero(), |fees, signed_transaction| {
+            fees.saturating_add(signed_transaction.transaction.fee)
+        })
+    }
+
+    /// Recomputes the Merkle root from the block body and checks it
+    /// against the header. A body whose tree is mutated is rejected even
+    /// when the root matches, since the header may well be valid with the
+    /// unmutated body.
+    pub fn validate_merkle_root(&self) -> Result<(), BlockError> {
+        let merkle_tree = MerkleTree::build_tree(&self.get_deseralized_transactions()?);
+        if merkle_tree.mutated {
+            return Err(BlockError::MutatedMerkleTree);
+        }
+        if merkle_tree.get_root() != Some(self.header.merkle_root) {
+            return Err(BlockError::BadMerkleRoot);
+        }
+        Ok(())
+    }
+
+    /// Proof that the transaction at `index` is in this block, checked
+    /// against the header's `merkle_root` with `verify_proof`.
+    pub fn prove_transaction(&self, index: usize) -> Result<Option<MerkleProof>, BlockError> {
+        Ok(MerkleTree::build_tree(&self.get_deseralized_transactions()?).prove(index))
+    }
 
-        digest(hash_string)
+    /// Checks that every single-key transaction in the block carries a valid
+    /// signature from its sender, reporting the index of the first one that
+    /// does not. Multisig transactions need their account's registered
+    /// policy, so they are checked when the block is applied to the state.
+    ///
+    /// Signatures are verified in parallel on the rayon thread pool, and
+    /// those found in `cache` were already verified and are skipped.
+    pub fn validate_signatures(
+        &self,
+        chain_id: u64,
+        cache: Option<&SignatureCache>,
+    ) -> Result<(), BlockError> {
+        let transactions = self.get_deseralized_transactions()?;
+        let invalid_index = transactions.par_iter().position_first(|signed_transaction| {
+            !cache.is_some_and(|cache| cache.contains(signed_transaction, chain_id))
+                && !Block::has_valid_signature(signed_transaction, chain_id)
+        });
+        Block::signature_result(invalid_index)
     }
 
-    pub fn get_deseralized_transactions(&self) -> Vec<Transaction> {
-        let mut deserialized_transactions: Vec<Transaction>  = vec![];
-        for transaction in self.transactions.iter() {
-        deserialized_transactions.push(Transaction::deseralize(transaction));
+    /// Same checks as `validate_signatures`, one transaction after the other
+    /// on the calling thread and without a cache. This is the baseline the
+    /// parallel stage is measured against.
+    pub fn validate_signatures_serially(&self, chain_id: u64) -> Result<(), BlockError> {
+        let invalid_index = self
+            .get_deseralized_transactions()?
+            .iter()
+            .position(|signed_transaction| !Block::has_valid_signature(signed_transaction, chain_id));
+        Block::signature_result(invalid_index)
     }
-        deserialized_transactions
+
+    fn has_valid_signature(signed_transaction: &SignedTransaction, chain_id: u64) -> bool {
+        matches!(signed_transaction.transaction.from, Sender::Multisig(_))
+            || signed_transaction.verify_signature(chain_id)
+    }
+
+    fn signature_result(invalid_index: Option<usize>) -> Result<(), BlockError> {
+        match invalid_index {
+            Some(index) => Err(BlockError::InvalidTransaction {
+                index,
+                error: TransactionError::BadSignature,
+            }),
+            None => Ok(()),
+        }
     }
 }
 
@@ -99,7 +356,7 @@ impl Block {
 pub struct MerkleNode {
     pub left: Option<Box<MerkleNode>>,
     pub right: Option<Box<MerkleNode>>,
-    pub value: String,
+    pub value: MerkleHash,
 }
 
 impl MerkleNode {
@@ -107,46 +364,72 @@ impl MerkleNode {
         digest(val)
     }
 
-    pub fn get_value(&self) -> &str {
+    pub fn get_value(&self) -> &MerkleHash {
         &self.value
     }
 }
 
+/// Binary Merkle tree over the encoded transactions of a block. A level
+/// with an odd number of nodes pairs its last node with itself.
+///
+/// That rule lets a body ending in a repeated run of transactions hash to
+/// the same root as the body without the repetition (CVE-2012-2459). Two
+/// distinct siblings can only hash alike if their subtrees are identical,
+/// so `mutated` records whether any real pair of siblings was equal, and
+/// such a body is never accepted.
 #[derive(Debug)]
 pub struct MerkleTree {
     pub root: Option<Box<MerkleNode>>,
+    pub leaf_count: usize,
+    pub mutated: bool,
+}
+
+/// Sibling hashes linking the leaf at `index` to the root, from the leaf
+/// up. Siblings that are the node itself, under the odd-node rule, are left
+/// out since they are implied by `index` and `leaf_count`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub siblings: Vec<MerkleHash>,
 }
 
 impl MerkleTree {
-    pub fn build_tree(transactions: &Vec<Transaction>) -> Self {
+    pub fn build_tree(transactions: &[SignedTransaction]) -> Self {
         if transactions.is_empty() {
             let default_root = MerkleNode {
                 left: None,
                 right: None,
-                value: String::from("")
+                value: MerkleHash::default(),
+            };
+            return Self {
+                root: Some(Box::new(default_root)),
+                leaf_count: 0,
+                mutated: false,
             };
-            return Self { root: Some(Box::new(default_root)) };
         }
         let mut nodes: Vec<MerkleNode> = transactions
             .iter()
             .map(|tx| MerkleNode {
                 left: None,
                 right: None,
-                value: digest(&convert_transaction_to_string(tx)),
+                value: MerkleTree::leaf_hash(tx),
             })
             .collect();
 
+        let mut mutated = false;
         while nodes.len() > 1 {
             let mut next_level: Vec<MerkleNode> = Vec::new();
             let mut i = 0;
             while i < nodes.len() {
                 let left = nodes[i].clone();
                 let right = if i + 1 < nodes.len() {
+                    mutated |= nodes[i + 1].value == left.value;
                     nodes[i + 1].clone()
                 } else {
                     left.clone()
                 };
-                let combined_hash = digest(&(left.value.clone() + &right.value));
+                let combined_hash = MerkleTree::node_hash(&left.value, &right.value);
                 let parent = MerkleNode {
                     left: Some(Box::new(left)),
                     right: Some(Box::new(right)),
@@ -160,14 +443,92 @@ impl MerkleTree {
 
         Self {
             root: Some(Box::new(nodes[0].clone())),
+            leaf_count: transactions.len(),
+            mutated,
         }
     }
 
-    pub fn get_root(&self) -> Option<String> {
-        if let Some(ref root) = self.root {
-            Some(root.value.clone())
-        } else {
-            None
+    pub fn get_root(&self) -> Option<MerkleHash> {
+        self.root.as_ref().map(|root| root.value)
+    }
+
+    pub fn leaf_hash(transaction: &SignedTransaction) -> MerkleHash {
+        MerkleHash::digest(&transaction.encode())
+    }
+
+    pub fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+        let mut preimage = Vec::new();
+        left.encode_to(&mut preimage);
+        right.encode_to(&mut preimage);
+        MerkleHash::digest(&preimage)
+    }
+
+    /// Proof that the transaction at `index` is part of the tree, or `None`
+    /// if there is no such leaf.
+    pub fn prove(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count {
+            return None;
         }
+        // Level widths from the leaves up, to tell duplicated nodes apart.
+        let mut widths = vec![self.leaf_count];
+        while widths[widths.len() - 1] > 1 {
+            widths.push(widths[widths.len() - 1].div_ceil(2));
+        }
+        let mut siblings = Vec::new();
+        let mut node = self.root.as_deref()?;
+        for level in (0..widths.len() - 1).rev() {
+            let position = index >> level;
+            let (left, right) = (node.left.as_deref()?, node.right.as_deref()?);
+            let (next, sibling) = if position.is_multiple_of(2) {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            if !position.is_multiple_of(2) || position + 1 < widths[level] {
+                siblings.push(sibling.value);
+            }
+            node = next;
+        }
+        siblings.reverse();
+        Some(MerkleProof {
+            index,
+            leaf_count: self.leaf_count,
+            siblings,
+        })
+    }
+}
+
+/// Checks that `leaf` is the transaction at `proof.index` of the block
+/// whose Merkle root is `root`. Only the node without a right neighbour is
+/// paired with itself; a proof pairing any other node with an equal sibling
+/// describes a mutated tree and fails. The root does not commit to the
+/// number of leaves, so `proof.leaf_count` is only checked where it shapes
+/// the path, along the right edge of the tree.
+pub fn verify_proof(root: &MerkleHash, leaf: &SignedTransaction, proof: &MerkleProof) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+    let mut siblings = proof.siblings.iter();
+    let mut hash = MerkleTree::leaf_hash(leaf);
+    let mut position = proof.index;
+    let mut width = proof.leaf_count;
+    while width > 1 {
+        let is_last_odd_node = position.is_multiple_of(2) && position + 1 == width;
+        hash = if is_last_odd_node {
+            MerkleTree::node_hash(&hash, &hash)
+        } else {
+            let sibling = match siblings.next() {
+                Some(sibling) if *sibling != hash => sibling,
+                _ => return false,
+            };
+            if position.is_multiple_of(2) {
+                MerkleTree::node_hash(&hash, sibling)
+            } else {
+                MerkleTree::node_hash(sibling, &hash)
+            }
+        };
+        position /= 2;
+        width = width.div_ceil(2);
     }
+    siblings.next().is_none() && hash == *root
 }
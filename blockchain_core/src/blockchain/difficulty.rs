@@ -0,0 +1,283 @@
+use super::block::Header;
+use primitive_types::{U256, U512};
+use serde::{Deserialize, Serialize};
+
+/// Height of the block anchoring rules that need one: the first block mined
+/// on the chain. The genesis timestamp is fixed in the chain spec, often long
+/// before the chain starts, so anchoring at genesis would put every chain
+/// behind schedule from its first block.
+pub const ANCHOR_HEIGHT: u64 = 1;
+
+/// What a retarget rule knows about one ancestor block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockTiming {
+    pub height: u64,
+    pub timestamp: u64,
+    pub target: U256,
+}
+
+impl From<&Header> for BlockTiming {
+    fn from(header: &Header) -> Self {
+        Self {
+            height: header.height,
+            timestamp: header.timestamp,
+            target: header.difficulty,
+        }
+    }
+}
+
+/// Inputs for computing the target of the child of `ancestors.last()`.
+pub struct RetargetContext<'a> {
+    /// The `window()` most recent ancestors, oldest first, ending with the parent.
+    pub ancestors: &'a [BlockTiming],
+    /// Block of the branch at `ANCHOR_HEIGHT`, only provided when
+    /// `needs_anchor()` is true and the parent is at least that high.
+    pub anchor: Option<BlockTiming>,
+    pub target_duration_between_blocks: u64,
+}
+
+/// A difficulty adjustment algorithm (DAA). Implementations must be pure
+/// functions of the context so every node derives the same target.
+pub trait DifficultyAlgorithm {
+    /// Number of ancestors, parent included, the rule looks at.
+    fn window(&self) -> usize;
+
+    /// Whether the rule needs the branch's block at `ANCHOR_HEIGHT`.
+    fn needs_anchor(&self) -> bool {
+        false
+    }
+
+    fn next_target(&self, context: &RetargetContext) -> U256;
+}
+
+/// Selectable, serializable set of the algorithms shipped with the crate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DifficultyAdjustment {
+    Step(StepAdjustment),
+    PeriodRetarget(PeriodRetarget),
+    Lwma(Lwma),
+    Asert(Asert),
+}
+
+impl DifficultyAlgorithm for DifficultyAdjustment {
+    fn window(&self) -> usize {
+        match self {
+            DifficultyAdjustment::Step(algorithm) => algorithm.window(),
+            DifficultyAdjustment::PeriodRetarget(algorithm) => algorithm.window(),
+            DifficultyAdjustment::Lwma(algorithm) => algorithm.window(),
+            DifficultyAdjustment::Asert(algorithm) => algorithm.window(),
+        }
+    }
+
+    fn needs_anchor(&self) -> bool {
+        match self {
+            DifficultyAdjustment::Step(algorithm) => algorithm.needs_anchor(),
+            DifficultyAdjustment::PeriodRetarget(algorithm) => algorithm.needs_anchor(),
+            DifficultyAdjustment::Lwma(algorithm) => algorithm.needs_anchor(),
+            DifficultyAdjustment::Asert(algorithm) => algorithm.needs_anchor(),
+        }
+    }
+
+    fn next_target(&self, context: &RetargetContext) -> U256 {
+        match self {
+            DifficultyAdjustment::Step(algorithm) => algorithm.next_target(context),
+            DifficultyAdjustment::PeriodRetarget(algorithm) => algorithm.next_target(context),
+            DifficultyAdjustment::Lwma(algorithm) => algorithm.next_target(context),
+            DifficultyAdjustment::Asert(algorithm) => algorithm.next_target(context),
+        }
+    }
+}
+
+impl DifficultyAdjustment {
+    /// Target of the child of the newest header `ancestor_headers(count)`
+    /// returns, given that it yields that header and up to `count - 1` of
+    /// its ancestors, oldest first, and that `anchor_header()` yields its
+    /// ancestor at `ANCHOR_HEIGHT`. Full nodes and light clients both derive
+    /// targets through this, from blocks and from bare headers respectively.
+    pub fn next_target_from_headers<'a>(
+        &self,
+        ancestor_headers: impl FnOnce(usize) -> Vec<&'a Header>,
+        anchor_header: impl FnOnce() -> Option<&'a Header>,
+        target_duration_between_blocks: u64,
+    ) -> U256 {
+        let ancestors: Vec<BlockTiming> = ancestor_headers(self.window().max(1))
+            .into_iter()
+            .map(BlockTiming::from)
+            .collect();
+        let anchor = if self.needs_anchor() {
+            anchor_header().map(BlockTiming::from)
+        } else {
+            None
+        };
+        self.next_target(&RetargetContext {
+            ancestors: &ancestors,
+            anchor,
+            target_duration_between_blocks,
+        })
+    }
+}
+
+/// Every `interval` blocks, move the target by 2% towards the block time,
+/// leaving it alone while the average spacing is within 5% of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepAdjustment {
+    pub interval: u64,
+}
+
+impl DifficultyAlgorithm for StepAdjustment {
+    fn window(&self) -> usize {
+        self.interval as usize
+    }
+
+    fn next_target(&self, context: &RetargetContext) -> U256 {
+        let parent = context.ancestors.last().expect("Parent block to be provided");
+        if !is_retarget_height(parent, self.interval) || context.ancestors.len() < 2 {
+            return parent.target;
+        }
+        let average_production_time =
+            timespan(context.ancestors) / (context.ancestors.len() as u64 - 1);
+        let block_time = context.target_duration_between_blocks;
+        let difficulty_variation = parent.target / 50;
+        if average_production_time < block_time * 95 / 100 {
+            parent.target - difficulty_variation
+        } else if average_production_time > block_time * 105 / 100 {
+            parent.target.saturating_add(difficulty_variation)
+        } else {
+            parent.target
+        }
+    }
+}
+
+/// Bitcoin-style retarget: every `interval` blocks, scale the target by
+/// actual / expected timespan of the period, clamped to a factor of 4.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeriodRetarget {
+    pub interval: u64,
+}
+
+impl DifficultyAlgorithm for PeriodRetarget {
+    fn window(&self) -> usize {
+        self.interval as usize
+    }
+
+    fn next_target(&self, context: &RetargetContext) -> U256 {
+        let parent = context.ancestors.last().expect("Parent block to be provided");
+        if !is_retarget_height(parent, self.interval) || context.ancestors.len() < 2 {
+            return parent.target;
+        }
+        let expected_timespan = (context.ancestors.len() as u64 - 1)
+            * context.target_duration_between_blocks.max(1);
+        let actual_timespan = timespan(context.ancestors)
+            .clamp(expected_timespan / 4, expected_timespan * 4)
+            .max(1);
+        scale_target(parent.target, actual_timespan as u128, expected_timespan as u128)
+    }
+}
+
+/// Linearly weighted moving average (zawy12's LWMA): every block, the
+/// average target of the last `window` blocks is scaled by their solve
+/// times, weighting recent blocks the most.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lwma {
+    pub window: u64,
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn window(&self) -> usize {
+        // N solve times need N + 1 timestamps.
+        self.window as usize + 1
+    }
+
+    fn next_target(&self, context: &RetargetContext) -> U256 {
+        let parent = context.ancestors.last().expect("Parent block to be provided");
+        let solve_time_count = context.ancestors.len() as u128 - 1;
+        if solve_time_count < 2 {
+            return parent.target;
+        }
+        let block_time = context.target_duration_between_blocks.max(1) as u128;
+        let mut weighted_solve_times: u128 = 0;
+        let mut target_sum = U512::zero();
+        for (i, pair) in context.ancestors.windows(2).enumerate() {
+            // Solve times are clamped so one bad timestamp cannot swing the target.
+            let solve_time = (pair[1].timestamp.saturating_sub(pair[0].timestamp) as u128)
+                .clamp(1, 6 * block_time);
+            weighted_solve_times += (i as u128 + 1) * solve_time;
+            target_sum += U512::from(pair[1].target);
+        }
+        let average_target = target_sum / U512::from(solve_time_count);
+        let expected_weighted_solve_times =
+            block_time * solve_time_count * (solve_time_count + 1) / 2;
+        let next_target = average_target * U512::from(weighted_solve_times)
+            / U512::from(expected_weighted_solve_times);
+        clamp_target(next_target)
+    }
+}
+
+/// Absolutely scheduled exponentially rising targets (ASERT, as in
+/// aserti3-2d): the target doubles for every `half_life` seconds the
+/// branch is behind schedule relative to its block at `ANCHOR_HEIGHT`, and
+/// halves for every `half_life` seconds it is ahead. Blocks up to the
+/// anchor keep the target of their parent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Asert {
+    pub half_life: u64,
+}
+
+impl DifficultyAlgorithm for Asert {
+    fn window(&self) -> usize {
+        1
+    }
+
+    fn needs_anchor(&self) -> bool {
+        true
+    }
+
+    fn next_target(&self, context: &RetargetContext) -> U256 {
+        let parent = context.ancestors.last().expect("Parent block to be provided");
+        let anchor = context.anchor.unwrap_or(*parent);
+        let time_delta = parent.timestamp as i128 - anchor.timestamp as i128;
+        let height_delta = parent.height.saturating_sub(anchor.height) as i128;
+        let schedule_delta =
+            time_delta - context.target_duration_between_blocks as i128 * height_delta;
+        // 16.16 fixed point exponent, split into whole shifts and a fraction.
+        let exponent = schedule_delta * 65536 / self.half_life.max(1) as i128;
+        let shifts = exponent >> 16;
+        let fraction = (exponent & 0xffff) as u128;
+        // Cubic approximation of 2^fraction - 1, scaled by 2^16.
+        let factor = 65536
+            + ((195_766_423_245_049 * fraction
+                + 971_821_376 * fraction * fraction
+                + 5_127 * fraction * fraction * fraction
+                + (1 << 47))
+                >> 48);
+        // anchor.target * factor < 2^273, so shifting left by up to 238 bits
+        // still fits in a U512 and saturates to U256::MAX when clamped.
+        let mut next_target = U512::from(anchor.target) * U512::from(factor);
+        let shifts = shifts - 16;
+        if shifts < 0 {
+            next_target >>= (-shifts).min(511) as usize;
+        } else {
+            next_target <<= shifts.min(238) as usize;
+        }
+        clamp_target(next_target)
+    }
+}
+
+fn is_retarget_height(parent: &BlockTiming, interval: u64) -> bool {
+    interval >= 2 && (parent.height + 1).is_multiple_of(interval)
+}
+
+fn timespan(ancestors: &[BlockTiming]) -> u64 {
+    let first_timestamp = ancestors.first().map_or(0, |timing| timing.timestamp);
+    let last_timestamp = ancestors.last().map_or(0, |timing| timing.timestamp);
+    last_timestamp.saturating_sub(first_timestamp)
+}
+
+fn scale_target(target: U256, numerator: u128, denominator: u128) -> U256 {
+    clamp_target(U512::from(target) * U512::from(numerator) / U512::from(denominator))
+}
+
+/// Targets stay within `[1, U256::MAX]`.
+fn clamp_target(target: U512) -> U256 {
+    U256::try_from(target).unwrap_or(U256::MAX).max(U256::one())
+}
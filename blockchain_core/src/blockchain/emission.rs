@@ -0,0 +1,101 @@
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+/// Number of bits a `U256` reward can be shifted right before it is zero.
+const REWARD_BITS: u64 = 256;
+
+/// How many new coins the coinbase of each block may mint. The genesis
+/// block mints nothing; its supply comes from the chain spec allocations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EmissionSchedule {
+    /// Bitcoin-style: blocks 1 to `interval` pay `initial_reward`, and the
+    /// reward halves every `interval` blocks after that until it reaches 0.
+    Halving { initial_reward: U256, interval: u64 },
+    /// Smooth decay: each block mints `1 / decay` of what is left of
+    /// `max_supply`, rounded down, so issuance never exceeds `max_supply`.
+    SmoothDecay { max_supply: U256, decay: u64 },
+}
+
+impl EmissionSchedule {
+    /// Reward the coinbase of the block at `height` must claim. Smooth decay
+    /// replays every earlier block; chains use `reward_after` with the
+    /// issuance they keep per block instead.
+    pub fn reward_at(&self, height: u64) -> U256 {
+        match self {
+            EmissionSchedule::Halving { .. } => self.reward_after(height, U256::zero()),
+            EmissionSchedule::SmoothDecay { .. } => {
+                self.reward_after(height, self.issued_through(height.saturating_sub(1)))
+            }
+        }
+    }
+
+    /// Reward of the block at `height` once the blocks below it minted
+    /// `issued_before` coins, in constant time.
+    pub fn reward_after(&self, height: u64, issued_before: U256) -> U256 {
+        if height == 0 {
+            return U256::zero();
+        }
+        match self {
+            EmissionSchedule::Halving {
+                initial_reward,
+                interval,
+            } => halved(*initial_reward, (height - 1) / (*interval).max(1)),
+            EmissionSchedule::SmoothDecay { max_supply, decay } => {
+                max_supply.saturating_sub(issued_before) / (*decay).max(1)
+            }
+        }
+    }
+
+    /// Coins minted by the blocks from height 1 to `height`, both included.
+    pub fn issued_through(&self, height: u64) -> U256 {
+        match self {
+            EmissionSchedule::Halving {
+                initial_reward,
+                interval,
+            } => {
+                let interval = (*interval).max(1);
+                let completed_eras = height / interval;
+                let issued: U256 = (0..completed_eras.min(REWARD_BITS))
+                    .map(|era| halved(*initial_reward, era).saturating_mul(U256::from(interval)))
+                    .fold(U256::zero(), U256::saturating_add);
+                let current_era_blocks = U256::from(height % interval);
+                issued.saturating_add(
+                    halved(*initial_reward, completed_eras).saturating_mul(current_era_blocks),
+                )
+            }
+            EmissionSchedule::SmoothDecay { max_supply, decay } => {
+                let decay = U256::from((*decay).max(1));
+                let mut remaining = *max_supply;
+                for _ in 0..height {
+                    let reward = remaining / decay;
+                    if reward.is_zero() {
+                        break;
+                    }
+                    remaining -= reward;
+                }
+                *max_supply - remaining
+            }
+        }
+    }
+
+    /// Upper bound of what the schedule can ever mint, or `None` if it does
+    /// not fit in a `U256`.
+    pub fn max_issuance(&self) -> Option<U256> {
+        match self {
+            EmissionSchedule::Halving {
+                initial_reward,
+                interval,
+            } => initial_reward
+                .checked_mul(U256::from(*interval))?
+                .checked_mul(U256::from(2)),
+            EmissionSchedule::SmoothDecay { max_supply, .. } => Some(*max_supply),
+        }
+    }
+}
+
+fn halved(reward: U256, halvings: u64) -> U256 {
+    if halvings >= REWARD_BITS {
+        return U256::zero();
+    }
+    reward >> halvings as usize
+}
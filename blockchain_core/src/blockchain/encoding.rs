@@ -0,0 +1,555 @@
+use super::address::{Address, ADDRESS_LENGTH};
+use super::block::{Block, Coinbase, Header, Recipient, Sender, SignedTransaction, Transaction};
+use super::difficulty::DifficultyAdjustment;
+use super::emission::EmissionSchedule;
+use super::hash::{BlockHash, MerkleHash, StateHash, TxHash, HASH_LENGTH};
+use super::multisig::MultisigPolicy;
+use super::utils::convert_public_key_to_bytes;
+use super::AccountState;
+use k256::{ecdsa::Signature, PublicKey};
+use primitive_types::U256;
+use std::fmt;
+
+/// Length of a SEC1 compressed public key.
+pub const PUBLIC_KEY_LENGTH: usize = 33;
+/// Length of an ECDSA signature, `r || s`.
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// Why bytes could not be decoded. Decoding is strict: any input that
+/// `encode` would not have produced is rejected, so every value has
+/// exactly one encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    TrailingBytes(usize),
+    NonCanonicalInteger,
+    NonCanonicalLength,
+    InvalidPublicKey,
+    InvalidSignature,
+    InvalidUtf8,
+    UnknownTag(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "input ended before the value was complete"),
+            DecodeError::TrailingBytes(count) => {
+                write!(f, "{} bytes left after the value", count)
+            }
+            DecodeError::NonCanonicalInteger => {
+                write!(f, "integer is not in its shortest encoding")
+            }
+            DecodeError::NonCanonicalLength => write!(f, "length is not in its shortest encoding"),
+            DecodeError::InvalidPublicKey => write!(f, "bytes are not a compressed public key"),
+            DecodeError::InvalidSignature => write!(f, "bytes are not an ECDSA signature"),
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown variant tag {}", tag),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Canonical binary encoding, used for hashing, Merkle leaves and the
+/// P2P wire format.
+///
+/// - `u8`, `u32`, `u64` and `u128` are fixed-width big-endian.
+/// - `U256` is one length byte followed by the value in big-endian without
+///   leading zero bytes, so zero is the single byte `0`.
+/// - Lengths and item counts use the compact size format: values below
+///   `0xfd` take one byte, larger ones a `0xfd`, `0xfe` or `0xff` marker
+///   followed by a big-endian `u16`, `u32` or `u64`.
+/// - Hashes, public keys and signatures are written as-is, at their fixed
+///   lengths.
+/// - Enums and options are a tag byte naming the variant followed by its
+///   fields; unknown tags are rejected.
+pub trait Encode {
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError>;
+
+    /// Decodes a value that must span all of `bytes`.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let value = Self::decode_from(&mut decoder)?;
+        decoder.finish()?;
+        Ok(value)
+    }
+}
+
+/// Cursor over the bytes being decoded.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if count > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    /// Fails if any input is left.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            count => Err(DecodeError::TrailingBytes(count)),
+        }
+    }
+
+    fn take_length(&mut self) -> Result<usize, DecodeError> {
+        let (length, minimum) = match self.take_array::<1>()?[0] {
+            0xfd => (u64::from(u16::decode_from(self)?), 0xfd),
+            0xfe => (u64::from(u32::decode_from(self)?), 1 << 16),
+            0xff => (u64::decode_from(self)?, 1 << 32),
+            length => return Ok(usize::from(length)),
+        };
+        if length < minimum {
+            return Err(DecodeError::NonCanonicalLength);
+        }
+        // A length past the end of the input can never be satisfied.
+        usize::try_from(length)
+            .ok()
+            .filter(|length| *length <= self.remaining())
+            .ok_or(DecodeError::UnexpectedEnd)
+    }
+}
+
+fn encode_length(length: usize, out: &mut Vec<u8>) {
+    let length = length as u64;
+    if length < 0xfd {
+        out.push(length as u8);
+    } else if length <= u64::from(u16::MAX) {
+        out.push(0xfd);
+        (length as u16).encode_to(out);
+    } else if length <= u64::from(u32::MAX) {
+        out.push(0xfe);
+        (length as u32).encode_to(out);
+    } else {
+        out.push(0xff);
+        length.encode_to(out);
+    }
+}
+
+macro_rules! impl_fixed_width_integer {
+    ($($integer:ty),*) => {
+        $(
+            impl Encode for $integer {
+                fn encode_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+
+            impl Decode for $integer {
+                fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+                    Ok(<$integer>::from_be_bytes(decoder.take_array()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_width_integer!(u8, u16, u32, u64, u128);
+
+impl Encode for U256 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let bytes = self.to_big_endian();
+        let significant_bytes = &bytes[bytes.iter().take_while(|byte| **byte == 0).count()..];
+        out.push(significant_bytes.len() as u8);
+        out.extend_from_slice(significant_bytes);
+    }
+}
+
+impl Decode for U256 {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let length = usize::from(decoder.take_array::<1>()?[0]);
+        if length > 32 {
+            return Err(DecodeError::NonCanonicalInteger);
+        }
+        let bytes = decoder.take(length)?;
+        if bytes.first() == Some(&0) {
+            return Err(DecodeError::NonCanonicalInteger);
+        }
+        Ok(U256::from_big_endian(bytes))
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_length(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let length = decoder.take_length()?;
+        let bytes = decoder.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_length(self.len(), out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_to(out);
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (**self).encode_to(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        // Every item takes at least one byte, which bounds the allocation
+        // by the input size rather than by the claimed length.
+        let length = decoder.take_length()?;
+        let mut items = Vec::with_capacity(length);
+        for _ in 0..length {
+            items.push(T::decode_from(decoder)?);
+        }
+        Ok(items)
+    }
+}
+
+macro_rules! impl_hash_encoding {
+    ($($hash:ty),*) => {
+        $(
+            impl Encode for $hash {
+                fn encode_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(self.as_bytes());
+                }
+            }
+
+            impl Decode for $hash {
+                fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+                    Ok(<$hash>::from_bytes(decoder.take_array::<HASH_LENGTH>()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_hash_encoding!(BlockHash, TxHash, StateHash, MerkleHash);
+
+impl Encode for Address {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for Address {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Address::from_bytes(decoder.take_array::<ADDRESS_LENGTH>()?))
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend(convert_public_key_to_bytes(self));
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let bytes = decoder.take(PUBLIC_KEY_LENGTH)?;
+        PublicKey::from_sec1_bytes(bytes).map_err(|_| DecodeError::InvalidPublicKey)
+    }
+}
+
+impl Encode for Signature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_bytes());
+    }
+}
+
+impl Decode for Signature {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let bytes = decoder.take(SIGNATURE_LENGTH)?;
+        Signature::from_slice(bytes).map_err(|_| DecodeError::InvalidSignature)
+    }
+}
+
+/// `0` alone for `None`, or `1` then the value.
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode_to(out),
+            Some(value) => {
+                1u8.encode_to(out);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match u8::decode_from(decoder)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(decoder)?)),
+            tag => Err(DecodeError::UnknownTag(tag)),
+        }
+    }
+}
+
+impl Encode for MultisigPolicy {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.threshold.encode_to(out);
+        self.public_keys.encode_to(out);
+    }
+}
+
+impl Decode for MultisigPolicy {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(MultisigPolicy {
+            threshold: u8::decode_from(decoder)?,
+            public_keys: Vec::<PublicKey>::decode_from(decoder)?,
+        })
+    }
+}
+
+/// A tag byte naming the variant followed by its value.
+impl Encode for Sender {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Sender::Key(public_key) => {
+                0u8.encode_to(out);
+                public_key.encode_to(out);
+            }
+            Sender::Multisig(address) => {
+                1u8.encode_to(out);
+                address.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Sender {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match u8::decode_from(decoder)? {
+            0 => Ok(Sender::Key(PublicKey::decode_from(decoder)?)),
+            1 => Ok(Sender::Multisig(Address::decode_from(decoder)?)),
+            tag => Err(DecodeError::UnknownTag(tag)),
+        }
+    }
+}
+
+/// A tag byte naming the variant followed by its value.
+impl Encode for Recipient {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Recipient::Account(address) => {
+                0u8.encode_to(out);
+                address.encode_to(out);
+            }
+            Recipient::NewMultisig(policy) => {
+                1u8.encode_to(out);
+                policy.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Recipient {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match u8::decode_from(decoder)? {
+            0 => Ok(Recipient::Account(Address::decode_from(decoder)?)),
+            1 => Ok(Recipient::NewMultisig(MultisigPolicy::decode_from(decoder)?)),
+            tag => Err(DecodeError::UnknownTag(tag)),
+        }
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.from.encode_to(out);
+        self.to.encode_to(out);
+        self.amount.encode_to(out);
+        self.fee.encode_to(out);
+        self.nonce.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            from: Sender::decode_from(decoder)?,
+            to: Recipient::decode_from(decoder)?,
+            amount: U256::decode_from(decoder)?,
+            fee: U256::decode_from(decoder)?,
+            nonce: u128::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for SignedTransaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.transaction.encode_to(out);
+        self.signatures.encode_to(out);
+    }
+}
+
+impl Decode for SignedTransaction {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(SignedTransaction {
+            transaction: Transaction::decode_from(decoder)?,
+            signatures: Vec::<Signature>::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for Coinbase {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.recipient.encode_to(out);
+        self.reward.encode_to(out);
+        self.fees.encode_to(out);
+    }
+}
+
+impl Decode for Coinbase {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Coinbase {
+            recipient: Address::decode_from(decoder)?,
+            reward: U256::decode_from(decoder)?,
+            fees: U256::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for Header {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.version.encode_to(out);
+        self.height.encode_to(out);
+        self.nonce.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.prev_hash.encode_to(out);
+        self.difficulty.encode_to(out);
+        self.merkle_root.encode_to(out);
+        self.state_root.encode_to(out);
+        self.coinbase.encode_to(out);
+    }
+}
+
+impl Decode for Header {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Header {
+            version: u32::decode_from(decoder)?,
+            height: u64::decode_from(decoder)?,
+            nonce: u64::decode_from(decoder)?,
+            timestamp: u64::decode_from(decoder)?,
+            prev_hash: BlockHash::decode_from(decoder)?,
+            difficulty: U256::decode_from(decoder)?,
+            merkle_root: MerkleHash::decode_from(decoder)?,
+            state_root: StateHash::decode_from(decoder)?,
+            coinbase: Coinbase::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for AccountState {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.balance.encode_to(out);
+        self.nonce.encode_to(out);
+        self.multisig.encode_to(out);
+    }
+}
+
+impl Decode for AccountState {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(AccountState {
+            balance: U256::decode_from(decoder)?,
+            nonce: u128::decode_from(decoder)?,
+            multisig: Option::<MultisigPolicy>::decode_from(decoder)?,
+        })
+    }
+}
+
+/// The header followed by each transaction as length-prefixed bytes, so a
+/// block can be decoded without parsing the transactions it carries.
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.transactions.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Header::decode_from(decoder)?,
+            transactions: Vec::<Vec<u8>>::decode_from(decoder)?,
+        })
+    }
+}
+
+/// A tag byte naming the algorithm followed by its parameter.
+impl Encode for DifficultyAdjustment {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let (tag, parameter): (u8, u64) = match self {
+            DifficultyAdjustment::Step(algorithm) => (0, algorithm.interval),
+            DifficultyAdjustment::PeriodRetarget(algorithm) => (1, algorithm.interval),
+            DifficultyAdjustment::Lwma(algorithm) => (2, algorithm.window),
+            DifficultyAdjustment::Asert(algorithm) => (3, algorithm.half_life),
+        };
+        tag.encode_to(out);
+        parameter.encode_to(out);
+    }
+}
+
+impl Encode for EmissionSchedule {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            EmissionSchedule::Halving {
+                initial_reward,
+                interval,
+            } => {
+                0u8.encode_to(out);
+                initial_reward.encode_to(out);
+                interval.encode_to(out);
+            }
+            EmissionSchedule::SmoothDecay { max_supply, decay } => {
+                1u8.encode_to(out);
+                max_supply.encode_to(out);
+                decay.encode_to(out);
+            }
+        }
+    }
+}
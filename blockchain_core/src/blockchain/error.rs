@@ -0,0 +1,181 @@
+use super::encoding::DecodeError;
+use super::hash::{BlockHash, StateHash};
+use super::multisig::MAX_MULTISIG_KEYS;
+use primitive_types::U256;
+use std::fmt;
+
+/// Why a transaction cannot enter the mempool or be applied to the state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    Malformed(String),
+    BadSignature,
+    UnknownAccount,
+    NotMultisig,
+    InvalidMultisigPolicy,
+    AlreadyRegistered,
+    BadNonce { expected: u128, found: u128 },
+    InsufficientBalance { balance: U256, required: U256 },
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Malformed(reason) => write!(f, "malformed transaction: {}", reason),
+            TransactionError::BadSignature => write!(f, "transaction signature is invalid"),
+            TransactionError::UnknownAccount => write!(f, "sender account does not exist"),
+            TransactionError::NotMultisig => {
+                write!(f, "sender is not a registered multisig account")
+            }
+            TransactionError::InvalidMultisigPolicy => write!(
+                f,
+                "multisig policy needs 1 to {} distinct keys and a threshold between 1 and the key count",
+                MAX_MULTISIG_KEYS
+            ),
+            TransactionError::AlreadyRegistered => {
+                write!(f, "multisig account is already registered")
+            }
+            TransactionError::BadNonce { expected, found } => write!(
+                f,
+                "transaction nonce {} does not match the expected nonce {}",
+                found, expected
+            ),
+            TransactionError::InsufficientBalance { balance, required } => write!(
+                f,
+                "sender balance {} does not cover amount and fee {}",
+                balance, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl From<DecodeError> for TransactionError {
+    fn from(err: DecodeError) -> Self {
+        TransactionError::Malformed(err.to_string())
+    }
+}
+
+/// Why a block timestamp violates the consensus time rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimestampError {
+    NotAfterMedianTimePast { timestamp: u64, median_time_past: u64 },
+    TooFarInFuture { timestamp: u64, max_timestamp: u64 },
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampError::NotAfterMedianTimePast { timestamp, median_time_past } => write!(
+                f,
+                "block timestamp {} is not after the median time past {}",
+                timestamp, median_time_past
+            ),
+            TimestampError::TooFarInFuture { timestamp, max_timestamp } => write!(
+                f,
+                "block timestamp {} is ahead of the latest allowed timestamp {}",
+                timestamp, max_timestamp
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimestampError {}
+
+/// Why a block was rejected by validation or could not be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    Malformed(String),
+    AlreadyKnown,
+    KnownInvalid,
+    InvalidAncestor { hash: BlockHash },
+    GenesisMismatch { hash: BlockHash },
+    BadMerkleRoot,
+    MutatedMerkleTree,
+    BadCoinbase,
+    TooManyTransactions { max: usize, found: usize },
+    BadVersion { version: u32 },
+    UnknownParent { prev_hash: BlockHash },
+    BadHeight { expected: u64, found: u64 },
+    BadTarget { expected: U256, found: U256 },
+    OrphanTargetTooEasy { max_target: U256, found: U256 },
+    BadTimestamp(TimestampError),
+    InsufficientWork { hash: BlockHash, target: U256 },
+    InvalidTransaction { index: usize, error: TransactionError },
+    BadStateRoot { expected: StateHash, found: StateHash },
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::Malformed(reason) => write!(f, "malformed block: {}", reason),
+            BlockError::AlreadyKnown => write!(f, "block is already known"),
+            BlockError::KnownInvalid => write!(f, "block was already found to be invalid"),
+            BlockError::InvalidAncestor { hash } => {
+                write!(f, "block descends from invalid block {}", hash)
+            }
+            BlockError::GenesisMismatch { hash } => {
+                write!(f, "block {} is a genesis block of another chain", hash)
+            }
+            BlockError::BadMerkleRoot => write!(f, "block Merkle root does not match its body"),
+            BlockError::MutatedMerkleTree => {
+                write!(f, "block body repeats transactions to match another body's Merkle root")
+            }
+            BlockError::BadCoinbase => {
+                write!(f, "block coinbase does not match the block reward and fees")
+            }
+            BlockError::TooManyTransactions { max, found } => write!(
+                f,
+                "block carries {} transactions, more than the limit of {}",
+                found, max
+            ),
+            BlockError::BadVersion { version } => write!(f, "unsupported block version {}", version),
+            BlockError::UnknownParent { prev_hash } => {
+                write!(f, "parent block {} is unknown", prev_hash)
+            }
+            BlockError::BadHeight { expected, found } => write!(
+                f,
+                "block height {} does not follow its parent, expected {}",
+                found, expected
+            ),
+            BlockError::BadTarget { expected, found } => write!(
+                f,
+                "block target {} does not match the target {} required by its parent",
+                found, expected
+            ),
+            BlockError::OrphanTargetTooEasy { max_target, found } => write!(
+                f,
+                "orphan block target {} is easier than the easiest accepted {}",
+                found, max_target
+            ),
+            BlockError::BadTimestamp(err) => write!(f, "{}", err),
+            BlockError::InsufficientWork { hash, target } => {
+                write!(f, "block hash {} does not meet target {}", hash, target)
+            }
+            BlockError::InvalidTransaction { index, error } => {
+                write!(f, "transaction {} is invalid: {}", index, error)
+            }
+            BlockError::BadStateRoot { expected, found } => write!(
+                f,
+                "block state root {} does not match the state {} it leads to",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlockError::BadTimestamp(err) => Some(err),
+            BlockError::InvalidTransaction { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<TimestampError> for BlockError {
+    fn from(err: TimestampError) -> Self {
+        BlockError::BadTimestamp(err)
+    }
+}
@@ -0,0 +1,138 @@
+use primitive_types::U256;
+use rustc_hex::{FromHex, ToHex};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha256::digest;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of bytes in a SHA-256 digest.
+pub const HASH_LENGTH: usize = 32;
+
+/// Why a string could not be read as a 32-byte hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseHashError {
+    InvalidHex,
+    InvalidLength(usize),
+}
+
+impl fmt::Display for ParseHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHashError::InvalidHex => write!(f, "hash is not valid hex"),
+            ParseHashError::InvalidLength(length) => write!(
+                f,
+                "hash is {} bytes long, expected {}",
+                length, HASH_LENGTH
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseHashError {}
+
+/// Defines a SHA-256 hash newtype. The raw bytes are what gets stored and
+/// compared; hex is only used for display and for the serde representation,
+/// which keeps JSON map keys readable.
+macro_rules! define_hash {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+        pub struct $name([u8; HASH_LENGTH]);
+
+        impl $name {
+            pub const fn from_bytes(bytes: [u8; HASH_LENGTH]) -> Self {
+                Self(bytes)
+            }
+
+            /// SHA-256 of `preimage`.
+            pub fn digest(preimage: &[u8]) -> Self {
+                digest(preimage)
+                    .parse()
+                    .expect("SHA-256 digest to be 32 bytes of hex")
+            }
+
+            pub fn as_bytes(&self) -> &[u8; HASH_LENGTH] {
+                &self.0
+            }
+
+            /// The hash read as a big-endian integer.
+            pub fn to_u256(&self) -> U256 {
+                U256::from_big_endian(&self.0)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0.to_hex::<String>())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseHashError;
+
+            fn from_str(hex: &str) -> Result<Self, Self::Err> {
+                let bytes: Vec<u8> = hex.from_hex().map_err(|_| ParseHashError::InvalidHex)?;
+                let bytes: [u8; HASH_LENGTH] = bytes
+                    .try_into()
+                    .map_err(|bytes: Vec<u8>| ParseHashError::InvalidLength(bytes.len()))?;
+                Ok(Self(bytes))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_string())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let hex = String::deserialize(deserializer)?;
+                hex.parse().map_err(de::Error::custom)
+            }
+        }
+    };
+}
+
+define_hash!(
+    /// Hash of a block header, which identifies the block.
+    BlockHash
+);
+
+define_hash!(
+    /// Hash of a transaction signing payload, which is what the sender signs.
+    TxHash
+);
+
+define_hash!(
+    /// Hash of a node of the account state tree, or the key of an account in it.
+    StateHash
+);
+
+define_hash!(
+    /// Hash of a node of a block's transaction Merkle tree. The root is
+    /// what the header commits to.
+    MerkleHash
+);
+
+define_hash!(
+    /// Hash of a chain id and a whole signed transaction, signatures
+    /// included, which keys the signature cache.
+    SignatureCacheKey
+);
+
+impl BlockHash {
+    /// `prev_hash` of the first block: no block hashes to all zeros, so it
+    /// cannot collide with a real parent.
+    pub const GENESIS_PARENT: BlockHash = BlockHash([0; HASH_LENGTH]);
+
+    pub fn is_genesis_parent(&self) -> bool {
+        *self == Self::GENESIS_PARENT
+    }
+}
@@ -0,0 +1,250 @@
+use super::block::{Block, Header, BLOCK_VERSION};
+use super::difficulty::{DifficultyAdjustment, ANCHOR_HEIGHT};
+use super::emission::EmissionSchedule;
+use super::error::{BlockError, TimestampError};
+use super::hash::BlockHash;
+use super::Blockchain;
+use primitive_types::U256;
+
+/// Number of most recent hashes a block locator lists one by one before
+/// it starts skipping back exponentially.
+pub const LOCATOR_DENSE_HASHES: usize = 10;
+
+/// Chain spec parameters the header rules read.
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderRules<'a> {
+    /// Target of the first block after genesis.
+    pub initial_difficulty: U256,
+    pub difficulty_algorithm: &'a DifficultyAdjustment,
+    pub target_duration_between_blocks: u64,
+    pub emission: &'a EmissionSchedule,
+    pub median_time_past_window: usize,
+    pub max_future_block_time: u64,
+}
+
+/// A tree of stored headers whose heaviest branch is indexed by height.
+/// Full nodes and light clients implement the lookups, and every
+/// header-only consensus rule is derived from them here, so both follow
+/// exactly the same rules.
+pub trait HeaderChain {
+    /// Stored header of `hash`, on any branch.
+    fn get_header(&self, hash: &BlockHash) -> Option<&Header>;
+
+    /// Hash at `height` on the canonical chain.
+    fn get_hash_by_height(&self, height: u64) -> Option<&BlockHash>;
+
+    /// Coins minted by the coinbases from height 1 up to the stored header
+    /// `hash`.
+    fn get_issued(&self, hash: &BlockHash) -> Option<U256>;
+
+    fn header_rules(&self) -> HeaderRules<'_>;
+
+    /// Header at `height` on the canonical chain.
+    fn get_header_by_height(&self, height: u64) -> Option<&Header> {
+        self.get_hash_by_height(height)
+            .and_then(|hash| self.get_header(hash))
+    }
+
+    /// Returns true if `hash` is part of the canonical chain.
+    fn is_canonical(&self, hash: &BlockHash) -> bool {
+        self.get_header(hash)
+            .and_then(|header| self.get_hash_by_height(header.height))
+            .is_some_and(|canonical_hash| canonical_hash == hash)
+    }
+
+    /// Hash `depth` generations above `hash`, `hash` itself at depth 0.
+    /// Canonical headers are resolved through the height index, other
+    /// branches are walked back until they join it.
+    fn ancestor_hash(&self, hash: &BlockHash, depth: u64) -> Option<BlockHash> {
+        let mut hash = *hash;
+        let mut header = self.get_header(&hash)?;
+        let target_height = header.height.checked_sub(depth)?;
+        while header.height > target_height {
+            if self.is_canonical(&header.prev_hash) {
+                return self.get_hash_by_height(target_height).copied();
+            }
+            hash = header.prev_hash;
+            header = self.get_header(&hash)?;
+        }
+        Some(hash)
+    }
+
+    /// Hashes of `hash` and its ancestors from which a peer can tell where
+    /// its canonical chain leaves this branch: the `LOCATOR_DENSE_HASHES`
+    /// most recent one by one, then with a step doubling each time, always
+    /// ending with genesis.
+    fn block_locator(&self, hash: &BlockHash) -> Vec<BlockHash> {
+        let Some(height) = self.get_header(hash).map(|header| header.height) else {
+            return Vec::new();
+        };
+        let mut locator = Vec::new();
+        let mut depth = 0;
+        let mut step = 1;
+        while depth < height {
+            locator.extend(self.ancestor_hash(hash, depth));
+            if locator.len() >= LOCATOR_DENSE_HASHES {
+                step *= 2;
+            }
+            depth += step;
+        }
+        locator.extend(self.ancestor_hash(hash, height));
+        locator
+    }
+
+    /// Height of the first hash of `locator` on the canonical chain, the
+    /// last block a peer sending it shares with us, or 0 if none is.
+    fn locate_fork(&self, locator: &[BlockHash]) -> u64 {
+        locator
+            .iter()
+            .find(|hash| self.is_canonical(hash))
+            .and_then(|hash| self.get_header(hash))
+            .map_or(0, |header| header.height)
+    }
+
+    /// Headers of `hash` and up to `count - 1` of its ancestors, oldest first.
+    fn get_ancestor_headers(&self, hash: &BlockHash, count: usize) -> Vec<&Header> {
+        let mut headers: Vec<&Header> = Vec::new();
+        let mut current_hash = hash;
+        while headers.len() < count {
+            let header = match self.get_header(current_hash) {
+                Some(header) => header,
+                None => break,
+            };
+            headers.push(header);
+            current_hash = &header.prev_hash;
+        }
+        headers.reverse();
+        headers
+    }
+
+    /// Median timestamp of `prev_hash` and its ancestors within the
+    /// median-time-past window, or `None` for children of the genesis marker.
+    fn get_median_time_past(&self, prev_hash: &BlockHash) -> Option<u64> {
+        let window = self.header_rules().median_time_past_window.max(1);
+        let mut timestamps: Vec<u64> = self
+            .get_ancestor_headers(prev_hash, window)
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        if timestamps.is_empty() {
+            return None;
+        }
+        timestamps.sort_unstable();
+        Some(timestamps[timestamps.len() / 2])
+    }
+
+    /// Height of a child of `prev_hash`, or `None` if the parent is unknown.
+    fn get_next_height(&self, prev_hash: &BlockHash) -> Option<u64> {
+        if prev_hash.is_genesis_parent() {
+            return Some(0);
+        }
+        self.get_header(prev_hash).map(|parent| parent.height + 1)
+    }
+
+    /// Target a child of `prev_hash` must be mined against, as computed by
+    /// the difficulty algorithm. It only depends on the parent's ancestry, so
+    /// every node computes the same value no matter which forks it has seen
+    /// or in which order blocks arrived. Children of the genesis marker use
+    /// the initial target.
+    fn get_next_difficulty(&self, prev_hash: &BlockHash) -> U256 {
+        let rules = self.header_rules();
+        let Some(parent) = self.get_header(prev_hash) else {
+            return rules.initial_difficulty;
+        };
+        rules.difficulty_algorithm.next_target_from_headers(
+            |count| self.get_ancestor_headers(prev_hash, count),
+            || {
+                let anchor_hash =
+                    self.ancestor_hash(prev_hash, parent.height.checked_sub(ANCHOR_HEIGHT)?)?;
+                self.get_header(&anchor_hash)
+            },
+            rules.target_duration_between_blocks,
+        )
+    }
+
+    /// Reward the coinbase of the block at `height` on top of `prev_hash`
+    /// must claim.
+    fn block_reward_after(&self, prev_hash: &BlockHash, height: u64) -> U256 {
+        let emission = self.header_rules().emission;
+        match self.get_issued(prev_hash) {
+            Some(issued) => emission.reward_after(height, issued),
+            None => emission.reward_at(height),
+        }
+    }
+
+    /// Runs every check that needs the header and its ancestry only: it must
+    /// not be a second genesis, must use the current block version, claim
+    /// the height, target and block reward its parent chain requires, carry
+    /// a valid timestamp and meet its target. Fees can only be checked
+    /// against the body.
+    fn validate_header(&self, header: &Header) -> Result<(), BlockError> {
+        // The only block at height 0 is the one derived from the chain spec.
+        if header.prev_hash.is_genesis_parent() {
+            return Err(BlockError::GenesisMismatch {
+                hash: Block::hash_header(header),
+            });
+        }
+        if header.version != BLOCK_VERSION {
+            return Err(BlockError::BadVersion {
+                version: header.version,
+            });
+        }
+        let expected_height = self.get_next_height(&header.prev_hash).ok_or(
+            BlockError::UnknownParent {
+                prev_hash: header.prev_hash,
+            },
+        )?;
+        if header.height != expected_height {
+            return Err(BlockError::BadHeight {
+                expected: expected_height,
+                found: header.height,
+            });
+        }
+        let expected_target = self.get_next_difficulty(&header.prev_hash);
+        if header.difficulty != expected_target {
+            return Err(BlockError::BadTarget {
+                expected: expected_target,
+                found: header.difficulty,
+            });
+        }
+        if header.coinbase.reward != self.block_reward_after(&header.prev_hash, header.height) {
+            return Err(BlockError::BadCoinbase);
+        }
+        self.validate_timestamp(header)?;
+        validate_proof_of_work(header)
+    }
+
+    /// A block timestamp must be strictly after the median timestamp of its
+    /// last `median_time_past_window` ancestors and at most
+    /// `max_future_block_time` seconds ahead of local time.
+    fn validate_timestamp(&self, header: &Header) -> Result<(), TimestampError> {
+        if let Some(median_time_past) = self.get_median_time_past(&header.prev_hash) {
+            if header.timestamp <= median_time_past {
+                return Err(TimestampError::NotAfterMedianTimePast {
+                    timestamp: header.timestamp,
+                    median_time_past,
+                });
+            }
+        }
+        let max_timestamp = Blockchain::get_adjusted_time()
+            .saturating_add(self.header_rules().max_future_block_time);
+        if header.timestamp > max_timestamp {
+            return Err(TimestampError::TooFarInFuture {
+                timestamp: header.timestamp,
+                max_timestamp,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The header hash must not exceed the target the header claims.
+pub fn validate_proof_of_work(header: &Header) -> Result<(), BlockError> {
+    if header.meets_target() {
+        return Ok(());
+    }
+    Err(BlockError::InsufficientWork {
+        hash: Block::hash_header(header),
+        target: header.difficulty,
+    })
+}
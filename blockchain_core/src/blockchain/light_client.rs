@@ -0,0 +1,151 @@
+use super::block::{verify_proof, Block, Header, MerkleProof, SignedTransaction};
+use super::difficulty::DifficultyAdjustment;
+use super::emission::EmissionSchedule;
+use super::error::BlockError;
+use super::hash::BlockHash;
+use super::header_chain::{HeaderChain, HeaderRules};
+use super::spec::{ChainSpec, ChainSpecError};
+use super::{MAX_FUTURE_BLOCK_TIME, MEDIAN_TIME_PAST_WINDOW};
+use primitive_types::U256;
+use std::collections::HashMap;
+
+/// Header-only view of a chain, for nodes that cannot keep the accounts and
+/// block bodies. Every header goes through the `HeaderChain` checks full
+/// nodes run before looking at a body (version, height, target, coinbase
+/// reward, timestamp and proof of work) and the client follows the branch
+/// carrying the most work, exactly like a full node would. Transactions are then checked against the
+/// headers' Merkle roots with proofs served by full nodes.
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    pub chain_id: u64,
+    headers: HashMap<BlockHash, Header>,
+    hash_to_cumulative_work: HashMap<BlockHash, U256>,
+    /// Coins minted by the coinbases from height 1 up to each header.
+    hash_to_issued: HashMap<BlockHash, U256>,
+    /// Hashes of the heaviest header chain, indexed by height.
+    canonical_chain: Vec<BlockHash>,
+    initial_difficulty: U256,
+    target_duration_between_blocks: u64,
+    difficulty_algorithm: DifficultyAdjustment,
+    emission: EmissionSchedule,
+    median_time_past_window: usize,
+    max_future_block_time: u64,
+}
+
+impl LightClient {
+    /// Starts from the genesis header of `spec`, which is trusted as is.
+    pub fn from_spec(spec: &ChainSpec) -> Result<Self, ChainSpecError> {
+        spec.validate()?;
+        let genesis_header = spec.genesis_block().header;
+        let genesis_hash = Block::hash_header(&genesis_header);
+        let genesis_work = genesis_header.work();
+        Ok(Self {
+            chain_id: spec.chain_id,
+            headers: HashMap::from([(genesis_hash, genesis_header)]),
+            hash_to_cumulative_work: HashMap::from([(genesis_hash, genesis_work)]),
+            hash_to_issued: HashMap::from([(genesis_hash, U256::zero())]),
+            canonical_chain: vec![genesis_hash],
+            initial_difficulty: spec.initial_difficulty,
+            target_duration_between_blocks: spec.target_duration_between_blocks,
+            difficulty_algorithm: spec.difficulty_algorithm.clone(),
+            emission: spec.emission.clone(),
+            median_time_past_window: MEDIAN_TIME_PAST_WINDOW,
+            max_future_block_time: MAX_FUTURE_BLOCK_TIME,
+        })
+    }
+
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.canonical_chain[0]
+    }
+
+    /// Latest header of the heaviest chain.
+    pub fn tip(&self) -> &Header {
+        &self.headers[&self.canonical_chain[self.canonical_chain.len() - 1]]
+    }
+
+    pub fn get_cumulative_work(&self, hash: &BlockHash) -> Option<U256> {
+        self.hash_to_cumulative_work.get(hash).copied()
+    }
+
+    /// Number of blocks on the heaviest chain from `hash` to the tip, both
+    /// included, or `None` if `hash` is not on it.
+    pub fn confirmations(&self, hash: &BlockHash) -> Option<u64> {
+        if !self.is_canonical(hash) {
+            return None;
+        }
+        Some(self.tip().height - self.headers[hash].height + 1)
+    }
+
+    /// Validates `header` against its parent chain and stores it, switching
+    /// to its branch if that branch now carries the most work. Headers must
+    /// arrive parent first: there is no orphan pool.
+    pub fn add_header(&mut self, header: Header) -> Result<(), BlockError> {
+        let hash = Block::hash_header(&header);
+        if self.headers.contains_key(&hash) {
+            return Err(BlockError::AlreadyKnown);
+        }
+        self.validate_header(&header)?;
+
+        let total_work = self.hash_to_cumulative_work[&header.prev_hash] + header.work();
+        let tip_work = self.hash_to_cumulative_work[&self.canonical_chain[self.canonical_chain.len() - 1]];
+        self.hash_to_cumulative_work.insert(hash, total_work);
+        let issued = self.hash_to_issued[&header.prev_hash] + header.coinbase.reward;
+        self.hash_to_issued.insert(hash, issued);
+        self.headers.insert(hash, header);
+        if total_work > tip_work {
+            self.switch_tip(hash);
+        }
+        Ok(())
+    }
+
+    /// Checks that `transaction` is included in the block `block_hash` of
+    /// the heaviest chain, using a Merkle proof obtained from a full node.
+    pub fn verify_transaction(
+        &self,
+        block_hash: &BlockHash,
+        transaction: &SignedTransaction,
+        proof: &MerkleProof,
+    ) -> bool {
+        self.is_canonical(block_hash)
+            && verify_proof(&self.headers[block_hash].merkle_root, transaction, proof)
+    }
+
+    /// Makes `new_tip` the tip of the heaviest chain, replacing the
+    /// canonical headers above the point where its branch forks off.
+    fn switch_tip(&mut self, new_tip: BlockHash) {
+        let mut branch = Vec::new();
+        let mut hash = new_tip;
+        while !self.is_canonical(&hash) {
+            branch.push(hash);
+            hash = self.headers[&hash].prev_hash;
+        }
+        let fork_height = self.headers[&hash].height as usize;
+        self.canonical_chain.truncate(fork_height + 1);
+        self.canonical_chain.extend(branch.into_iter().rev());
+    }
+}
+
+impl HeaderChain for LightClient {
+    fn get_header(&self, hash: &BlockHash) -> Option<&Header> {
+        self.headers.get(hash)
+    }
+
+    fn get_hash_by_height(&self, height: u64) -> Option<&BlockHash> {
+        self.canonical_chain.get(usize::try_from(height).ok()?)
+    }
+
+    fn get_issued(&self, hash: &BlockHash) -> Option<U256> {
+        self.hash_to_issued.get(hash).copied()
+    }
+
+    fn header_rules(&self) -> HeaderRules<'_> {
+        HeaderRules {
+            initial_difficulty: self.initial_difficulty,
+            difficulty_algorithm: &self.difficulty_algorithm,
+            target_duration_between_blocks: self.target_duration_between_blocks,
+            emission: &self.emission,
+            median_time_past_window: self.median_time_past_window,
+            max_future_block_time: self.max_future_block_time,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use super::address::Address;
+use super::encoding::Encode;
+use super::error::TransactionError;
+use super::hash::TxHash;
+use k256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    PublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+/// Most keys a multisig account can be controlled by.
+pub const MAX_MULTISIG_KEYS: usize = 16;
+/// Prepended to the policy encoding before hashing it into an address, so
+/// a multisig address can never be the address of a single key.
+const MULTISIG_ADDRESS_TAG: &[u8] = b"multisig";
+
+/// Key set and threshold controlling a multisig account: a transaction
+/// spending from it needs valid signatures from `threshold` of the
+/// `public_keys`. The account lives at `address()`, which commits to the
+/// policy, and the policy is recorded in the account state when the
+/// account is registered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    pub threshold: u8,
+    pub public_keys: Vec<PublicKey>,
+}
+
+impl MultisigPolicy {
+    pub fn new(threshold: u8, public_keys: Vec<PublicKey>) -> Self {
+        Self {
+            threshold,
+            public_keys,
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        let mut preimage = MULTISIG_ADDRESS_TAG.to_vec();
+        self.encode_to(&mut preimage);
+        Address::from_preimage(&preimage)
+    }
+
+    /// A policy needs between 1 and `MAX_MULTISIG_KEYS` distinct keys and a
+    /// threshold no larger than its key count, and never zero.
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        let key_count = self.public_keys.len();
+        let has_duplicate_key = self
+            .public_keys
+            .iter()
+            .enumerate()
+            .any(|(index, public_key)| self.public_keys[..index].contains(public_key));
+        if self.threshold == 0
+            || usize::from(self.threshold) > key_count
+            || key_count > MAX_MULTISIG_KEYS
+            || has_duplicate_key
+        {
+            return Err(TransactionError::InvalidMultisigPolicy);
+        }
+        Ok(())
+    }
+
+    /// Checks that `signatures` are exactly `threshold` valid signatures of
+    /// `transaction_hash`, by distinct keys and in the order the keys are
+    /// listed in the policy.
+    pub fn verify(&self, transaction_hash: &TxHash, signatures: &[Signature]) -> bool {
+        if signatures.len() != usize::from(self.threshold) {
+            return false;
+        }
+        let mut public_keys = self.public_keys.iter();
+        signatures.iter().all(|signature| {
+            public_keys.any(|public_key| {
+                VerifyingKey::from(public_key)
+                    .verify(transaction_hash.as_bytes(), signature)
+                    .is_ok()
+            })
+        })
+    }
+}
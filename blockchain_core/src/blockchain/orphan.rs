@@ -0,0 +1,146 @@
+use super::block::Block;
+use super::hash::BlockHash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Most orphan blocks kept at once; the oldest is evicted past this.
+pub const MAX_ORPHAN_BLOCKS: usize = 100;
+/// Seconds an orphan block waits for its parent before being dropped.
+pub const MAX_ORPHAN_AGE: u64 = 20 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanBlock {
+    pub block: Block,
+    pub received_at: u64,
+}
+
+/// Blocks received before their parent, indexed by the parent hash they
+/// are waiting for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanPool {
+    pub max_orphans: usize,
+    pub max_age: u64,
+    orphans: HashMap<BlockHash, OrphanBlock>,
+    orphans_by_missing_parent: HashMap<BlockHash, Vec<BlockHash>>,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::new(MAX_ORPHAN_BLOCKS, MAX_ORPHAN_AGE)
+    }
+}
+
+impl OrphanPool {
+    pub fn new(max_orphans: usize, max_age: u64) -> Self {
+        Self {
+            max_orphans,
+            max_age,
+            orphans: HashMap::new(),
+            orphans_by_missing_parent: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.orphans.contains_key(hash)
+    }
+
+    /// Stores `block` under `hash` after dropping expired orphans, evicting
+    /// the oldest one if the pool is full.
+    pub fn insert(&mut self, hash: BlockHash, block: Block, now: u64) {
+        self.expire(now);
+        if self.max_orphans == 0 || self.orphans.contains_key(&hash) {
+            return;
+        }
+        if self.orphans.len() >= self.max_orphans {
+            let oldest_hash = self
+                .orphans
+                .iter()
+                .min_by_key(|(_, orphan)| orphan.received_at)
+                .map(|(hash, _)| *hash);
+            if let Some(oldest_hash) = oldest_hash {
+                self.remove(&oldest_hash);
+            }
+        }
+        self.orphans_by_missing_parent
+            .entry(block.header.prev_hash)
+            .or_default()
+            .push(hash);
+        self.orphans.insert(
+            hash,
+            OrphanBlock {
+                block,
+                received_at: now,
+            },
+        );
+    }
+
+    /// Drops every orphan that has waited longer than `max_age`.
+    pub fn expire(&mut self, now: u64) {
+        let expired_hashes: Vec<BlockHash> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| now.saturating_sub(orphan.received_at) > self.max_age)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired_hashes {
+            self.remove(&hash);
+        }
+    }
+
+    /// Removes and returns the orphans waiting for `parent_hash`, in arrival order.
+    pub fn take_children(&mut self, parent_hash: &BlockHash) -> Vec<(BlockHash, Block)> {
+        self.orphans_by_missing_parent
+            .remove(parent_hash)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|hash| {
+                self.orphans
+                    .remove(&hash)
+                    .map(|orphan| (hash, orphan.block))
+            })
+            .collect()
+    }
+
+    /// Drops every orphan descending from `hash`, returning how many were removed.
+    pub fn remove_descendants(&mut self, hash: &BlockHash) -> usize {
+        let mut removed_count = 0;
+        let mut parent_hashes = vec![*hash];
+        while let Some(parent_hash) = parent_hashes.pop() {
+            for (child_hash, _) in self.take_children(&parent_hash) {
+                removed_count += 1;
+                parent_hashes.push(child_hash);
+            }
+        }
+        removed_count
+    }
+
+    /// Parent hashes that orphans wait for and that are not orphans
+    /// themselves, i.e. the blocks to request from peers.
+    pub fn missing_ancestors(&self) -> Vec<BlockHash> {
+        self.orphans_by_missing_parent
+            .keys()
+            .filter(|parent_hash| !self.orphans.contains_key(*parent_hash))
+            .cloned()
+            .collect()
+    }
+
+    fn remove(&mut self, hash: &BlockHash) {
+        if let Some(orphan) = self.orphans.remove(hash) {
+            let parent_hash = &orphan.block.header.prev_hash;
+            if let Some(siblings) = self.orphans_by_missing_parent.get_mut(parent_hash) {
+                siblings.retain(|sibling_hash| sibling_hash != hash);
+                if siblings.is_empty() {
+                    self.orphans_by_missing_parent.remove(parent_hash);
+                }
+            }
+        }
+    }
+}
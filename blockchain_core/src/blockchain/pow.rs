@@ -0,0 +1,93 @@
+use super::block::Header;
+use super::Blockchain;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Hashes a worker tries between two reads of the clock.
+pub const TIMESTAMP_REFRESH_INTERVAL: u64 = 1 << 16;
+
+/// Proof-of-work search over `threads` worker threads. Worker `i` tries the
+/// nonces `start + i`, `start + i + threads`, ..., so the workers never
+/// hash the same header, and all of them stop as soon as one finds a
+/// header meeting its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowEngine {
+    pub threads: usize,
+}
+
+/// Header found by a search, with what it cost.
+#[derive(Debug, Clone)]
+pub struct PowSolution {
+    pub header: Header,
+    /// Headers hashed by all workers together, the solution included.
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl Default for PowEngine {
+    /// One worker per available core.
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |threads| threads.get()))
+    }
+}
+
+impl PowEngine {
+    /// At least one worker is always used.
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    /// Grinds the nonce of `header`, from its current value, until its hash
+    /// meets its target. Every `TIMESTAMP_REFRESH_INTERVAL` hashes a worker
+    /// moves its timestamp to the current time, never below `min_timestamp`.
+    pub fn solve(&self, header: &Header, min_timestamp: u64) -> PowSolution {
+        let start = Instant::now();
+        let found = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+        let solution: Mutex<Option<Header>> = Mutex::new(None);
+        let stride = self.threads as u64;
+        thread::scope(|scope| {
+            for worker in 0..stride {
+                let (found, hashes, solution) = (&found, &hashes, &solution);
+                let mut candidate = header.clone();
+                candidate.nonce = header.nonce.wrapping_add(worker);
+                scope.spawn(move || {
+                    let mut worker_hashes: u64 = 0;
+                    while !found.load(Ordering::Relaxed) {
+                        if worker_hashes > 0 && worker_hashes.is_multiple_of(TIMESTAMP_REFRESH_INTERVAL) {
+                            candidate.timestamp = Blockchain::get_adjusted_time().max(min_timestamp);
+                        }
+                        worker_hashes += 1;
+                        if candidate.meets_target() {
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *solution.lock().expect("No worker to panic") = Some(candidate);
+                            }
+                            break;
+                        }
+                        candidate.nonce = candidate.nonce.wrapping_add(stride);
+                    }
+                    hashes.fetch_add(worker_hashes, Ordering::Relaxed);
+                });
+            }
+        });
+        PowSolution {
+            header: solution
+                .into_inner()
+                .expect("No worker to panic")
+                .expect("Workers to stop only once a header is found"),
+            hashes: hashes.into_inner(),
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+impl PowSolution {
+    /// Hashes per second over the whole search.
+    pub fn hashrate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
@@ -0,0 +1,95 @@
+use super::block::SignedTransaction;
+use super::encoding::Encode;
+use super::hash::SignatureCacheKey;
+use std::collections::{HashMap, VecDeque};
+
+/// Most verified transactions remembered at once; the oldest is evicted past this.
+pub const MAX_SIGNATURE_CACHE_ENTRIES: usize = 50_000;
+
+/// Signed transactions whose signatures already verified, so that a block
+/// does not verify again what the mempool did. Entries are keyed by the
+/// chain id and the whole signed transaction, signatures included, so a
+/// transaction carrying other signatures never hits the cache.
+#[derive(Debug, Clone)]
+pub struct SignatureCache {
+    pub max_entries: usize,
+    /// Cached keys and the sequence number of their latest insertion.
+    entries: HashMap<SignatureCacheKey, u64>,
+    /// Keys with their sequence number, oldest first, for eviction. Removal
+    /// does not scan the queue: an item whose sequence number no longer
+    /// matches its entry is stale and skipped when it reaches the front.
+    insertion_order: VecDeque<(SignatureCacheKey, u64)>,
+    next_sequence: u64,
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new(MAX_SIGNATURE_CACHE_ENTRIES)
+    }
+}
+
+impl SignatureCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: HashMap::new(),
+            insertion_order: VecDeque::new(),
+            next_sequence: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, signed_transaction: &SignedTransaction, chain_id: u64) -> bool {
+        self.entries
+            .contains_key(&cache_key(signed_transaction, chain_id))
+    }
+
+    /// Remembers that the signatures of `signed_transaction` verified on the
+    /// chain `chain_id`, evicting the oldest entry if the cache is full.
+    pub fn insert(&mut self, signed_transaction: &SignedTransaction, chain_id: u64) {
+        let key = cache_key(signed_transaction, chain_id);
+        if self.max_entries == 0 || self.entries.contains_key(&key) {
+            return;
+        }
+        while self.entries.len() >= self.max_entries {
+            match self.insertion_order.pop_front() {
+                Some((oldest_key, sequence)) => {
+                    if self.entries.get(&oldest_key) == Some(&sequence) {
+                        self.entries.remove(&oldest_key);
+                    }
+                }
+                None => break,
+            }
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.entries.insert(key, sequence);
+        self.insertion_order.push_back((key, sequence));
+        // Stale items left by removals never outnumber the live ones.
+        if self.insertion_order.len() > 2 * self.entries.len() {
+            let entries = &self.entries;
+            self.insertion_order
+                .retain(|(key, sequence)| entries.get(key) == Some(sequence));
+        }
+    }
+
+    /// Forgets `signed_transaction`, once the block carrying it is connected
+    /// and its signatures will not be checked again.
+    pub fn remove(&mut self, signed_transaction: &SignedTransaction, chain_id: u64) {
+        self.entries
+            .remove(&cache_key(signed_transaction, chain_id));
+    }
+}
+
+fn cache_key(signed_transaction: &SignedTransaction, chain_id: u64) -> SignatureCacheKey {
+    let mut preimage = chain_id.encode();
+    signed_transaction.encode_to(&mut preimage);
+    SignatureCacheKey::digest(&preimage)
+}
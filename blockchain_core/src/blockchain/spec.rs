@@ -0,0 +1,289 @@
+use super::address::Address;
+use super::block::{Block, Coinbase, Header, BLOCK_VERSION};
+use super::difficulty::DifficultyAdjustment;
+use super::emission::EmissionSchedule;
+use super::encoding::Encode;
+use super::hash::{BlockHash, MerkleHash};
+use super::state_tree::state_root;
+use super::AccountState;
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+/// Why a chain specification could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainSpecError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ChainSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainSpecError::Io(reason) => write!(f, "cannot read chain spec: {}", reason),
+            ChainSpecError::Parse(reason) => write!(f, "cannot parse chain spec: {}", reason),
+            ChainSpecError::Invalid(reason) => write!(f, "invalid chain spec: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ChainSpecError {}
+
+/// Balance credited to `address` by the genesis block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenesisAllocation {
+    pub address: Address,
+    pub balance: U256,
+}
+
+/// Consensus parameters of a chain and the accounts funded at genesis,
+/// usually loaded from a JSON file. Every node loading the same spec
+/// derives the same genesis block, and any change to the spec changes the
+/// genesis hash, so nodes on different specs cannot share blocks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ChainSpecJson", into = "ChainSpecJson")]
+pub struct ChainSpec {
+    pub chain_id: u64,
+    /// Human-readable part of the chain's bech32 addresses.
+    pub address_prefix: String,
+    pub genesis_timestamp: u64,
+    pub initial_difficulty: U256,
+    pub emission: EmissionSchedule,
+    pub target_duration_between_blocks: u64,
+    pub max_transactions_per_block: usize,
+    pub difficulty_algorithm: DifficultyAdjustment,
+    pub allocations: Vec<GenesisAllocation>,
+}
+
+/// JSON form of a `ChainSpec`. Allocation addresses are bech32m text
+/// under the spec's own `address_prefix`, so a mistyped address fails
+/// its checksum instead of funding an account nobody holds.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainSpecJson {
+    chain_id: u64,
+    address_prefix: String,
+    genesis_timestamp: u64,
+    initial_difficulty: U256,
+    emission: EmissionSchedule,
+    target_duration_between_blocks: u64,
+    max_transactions_per_block: usize,
+    difficulty_algorithm: DifficultyAdjustment,
+    #[serde(default)]
+    allocations: Vec<GenesisAllocationJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GenesisAllocationJson {
+    address: String,
+    balance: U256,
+}
+
+impl TryFrom<ChainSpecJson> for ChainSpec {
+    type Error = ChainSpecError;
+
+    fn try_from(json: ChainSpecJson) -> Result<Self, Self::Error> {
+        let allocations = json
+            .allocations
+            .into_iter()
+            .map(|allocation| {
+                let address = Address::from_bech32(&allocation.address, &json.address_prefix)
+                    .map_err(|err| {
+                        ChainSpecError::Invalid(format!(
+                            "allocation address {}: {}",
+                            allocation.address, err
+                        ))
+                    })?;
+                Ok(GenesisAllocation {
+                    address,
+                    balance: allocation.balance,
+                })
+            })
+            .collect::<Result<_, ChainSpecError>>()?;
+        Ok(ChainSpec {
+            chain_id: json.chain_id,
+            address_prefix: json.address_prefix,
+            genesis_timestamp: json.genesis_timestamp,
+            initial_difficulty: json.initial_difficulty,
+            emission: json.emission,
+            target_duration_between_blocks: json.target_duration_between_blocks,
+            max_transactions_per_block: json.max_transactions_per_block,
+            difficulty_algorithm: json.difficulty_algorithm,
+            allocations,
+        })
+    }
+}
+
+impl From<ChainSpec> for ChainSpecJson {
+    fn from(spec: ChainSpec) -> Self {
+        let allocations = spec
+            .allocations
+            .iter()
+            .map(|allocation| GenesisAllocationJson {
+                address: allocation.address.to_bech32(&spec.address_prefix),
+                balance: allocation.balance,
+            })
+            .collect();
+        ChainSpecJson {
+            chain_id: spec.chain_id,
+            address_prefix: spec.address_prefix,
+            genesis_timestamp: spec.genesis_timestamp,
+            initial_difficulty: spec.initial_difficulty,
+            emission: spec.emission,
+            target_duration_between_blocks: spec.target_duration_between_blocks,
+            max_transactions_per_block: spec.max_transactions_per_block,
+            difficulty_algorithm: spec.difficulty_algorithm,
+            allocations,
+        }
+    }
+}
+
+impl ChainSpec {
+    pub fn from_json(json: &str) -> Result<Self, ChainSpecError> {
+        let spec: ChainSpec =
+            serde_json::from_str(json).map_err(|err| ChainSpecError::Parse(err.to_string()))?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ChainSpecError> {
+        let json = std::fs::read_to_string(path.as_ref()).map_err(|err| {
+            ChainSpecError::Io(format!("{}: {}", path.as_ref().display(), err))
+        })?;
+        Self::from_json(&json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Chain spec to be serialized")
+    }
+
+    /// Rejects parameters no chain can run with, accounts funded twice and
+    /// supplies that could overflow a balance.
+    pub fn validate(&self) -> Result<(), ChainSpecError> {
+        if !Address::is_valid_prefix(&self.address_prefix) {
+            return Err(ChainSpecError::Invalid(format!(
+                "address prefix {:?} is not a lower case bech32 prefix",
+                self.address_prefix
+            )));
+        }
+        if self.initial_difficulty.is_zero() {
+            return Err(ChainSpecError::Invalid(String::from(
+                "initial difficulty must be above zero",
+            )));
+        }
+        if self.target_duration_between_blocks == 0 {
+            return Err(ChainSpecError::Invalid(String::from(
+                "target duration between blocks must be above zero",
+            )));
+        }
+        if self.max_transactions_per_block == 0 {
+            return Err(ChainSpecError::Invalid(String::from(
+                "blocks must be allowed at least one transaction",
+            )));
+        }
+        match self.emission {
+            EmissionSchedule::Halving { interval: 0, .. } => {
+                return Err(ChainSpecError::Invalid(String::from(
+                    "halving interval must be above zero",
+                )));
+            }
+            EmissionSchedule::SmoothDecay { decay: 0, .. } => {
+                return Err(ChainSpecError::Invalid(String::from(
+                    "emission decay must be above zero",
+                )));
+            }
+            _ => {}
+        }
+        let mut funded_accounts = HashSet::new();
+        for allocation in self.allocations.iter() {
+            if !funded_accounts.insert(allocation.address) {
+                return Err(ChainSpecError::Invalid(format!(
+                    "account {} is allocated more than once",
+                    allocation.address.to_bech32(&self.address_prefix)
+                )));
+            }
+        }
+        self.allocations
+            .iter()
+            .try_fold(U256::zero(), |total, allocation| {
+                total.checked_add(allocation.balance)
+            })
+            .and_then(|allocated| allocated.checked_add(self.emission.max_issuance()?))
+            .ok_or(ChainSpecError::Invalid(String::from(
+                "allocations and emission exceed the largest representable supply",
+            )))?;
+        Ok(())
+    }
+
+    /// Accounts funded by the allocations, as the genesis block leaves them.
+    pub fn genesis_accounts(&self) -> HashMap<Address, AccountState> {
+        self.allocations
+            .iter()
+            .map(|allocation| {
+                (
+                    allocation.address,
+                    AccountState {
+                        balance: allocation.balance,
+                        nonce: 0,
+                        multisig: None,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Block at height 0. It carries no transactions and pays no reward:
+    /// its Merkle root commits to the whole spec instead, allocations
+    /// included, its state root to the funded accounts, and its coinbase
+    /// names the all-zero address as a placeholder recipient.
+    pub fn genesis_block(&self) -> Block {
+        let header = Header {
+            version: BLOCK_VERSION,
+            height: 0,
+            nonce: 0,
+            timestamp: self.genesis_timestamp,
+            prev_hash: BlockHash::GENESIS_PARENT,
+            difficulty: self.initial_difficulty,
+            merkle_root: MerkleHash::digest(&self.encode()),
+            state_root: state_root(&self.genesis_accounts()),
+            coinbase: Coinbase {
+                recipient: Address::default(),
+                reward: U256::zero(),
+                fees: U256::zero(),
+            },
+        };
+        Block {
+            header,
+            transactions: Vec::new(),
+        }
+    }
+
+    pub fn genesis_hash(&self) -> BlockHash {
+        Block::hash_header(&self.genesis_block().header)
+    }
+}
+
+impl Encode for GenesisAllocation {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.address.encode_to(out);
+        self.balance.encode_to(out);
+    }
+}
+
+impl Encode for ChainSpec {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.chain_id.encode_to(out);
+        self.address_prefix.encode_to(out);
+        self.genesis_timestamp.encode_to(out);
+        self.initial_difficulty.encode_to(out);
+        self.emission.encode_to(out);
+        self.target_duration_between_blocks.encode_to(out);
+        (self.max_transactions_per_block as u64).encode_to(out);
+        self.difficulty_algorithm.encode_to(out);
+        self.allocations.encode_to(out);
+    }
+}
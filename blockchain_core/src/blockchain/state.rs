@@ -0,0 +1,115 @@
+use super::address::Address;
+use super::AccountState;
+use serde::{Deserialize, Serialize};
+use serde_json_any_key::*;
+use std::collections::HashMap;
+
+/// Account states a block overwrote, captured when it was applied.
+/// `None` means the account did not exist before the block.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockUndo {
+    #[serde(with = "any_key_map")]
+    pub prior_accounts: HashMap<Address, Option<AccountState>>,
+}
+
+/// Account changes staged on top of the committed accounts. Reads fall
+/// through to the committed state and the first write to an account copies
+/// it into the overlay, so the committed map is left untouched until the
+/// caller takes the changes with `into_changes`. While a journal is open,
+/// the first write to each account also records its prior state.
+pub struct StateOverlay<'a> {
+    accounts: &'a HashMap<Address, AccountState>,
+    changes: HashMap<Address, Option<AccountState>>,
+    journal: Option<BlockUndo>,
+}
+
+impl<'a> StateOverlay<'a> {
+    pub fn new(accounts: &'a HashMap<Address, AccountState>) -> Self {
+        Self {
+            accounts,
+            changes: HashMap::new(),
+            journal: None,
+        }
+    }
+
+    pub fn get(&self, address: &Address) -> Option<&AccountState> {
+        match self.changes.get(address) {
+            Some(account) => account.as_ref(),
+            None => self.accounts.get(address),
+        }
+    }
+
+    pub fn get_mut(&mut self, address: &Address) -> Option<&mut AccountState> {
+        let account = self.get(address)?.clone();
+        self.record_prior_state(address);
+        self.changes
+            .entry(*address)
+            .or_insert(Some(account))
+            .as_mut()
+    }
+
+    /// Like `get_mut`, but stages an empty account if none exists yet.
+    pub fn get_or_create_mut(&mut self, address: &Address) -> &mut AccountState {
+        if self.get(address).is_none() {
+            self.record_prior_state(address);
+            self.changes.insert(
+                *address,
+                Some(AccountState::default()),
+            );
+        }
+        self.get_mut(address)
+            .expect("Account to be staged")
+    }
+
+    /// Every account as staged: committed accounts overridden by the changes.
+    pub fn accounts(&self) -> impl Iterator<Item = (&Address, &AccountState)> + '_ {
+        let unchanged_accounts = self
+            .accounts
+            .iter()
+            .filter(|(address, _)| !self.changes.contains_key(*address));
+        let changed_accounts = self
+            .changes
+            .iter()
+            .filter_map(|(address, account)| Some((address, account.as_ref()?)));
+        unchanged_accounts.chain(changed_accounts)
+    }
+
+    /// Starts recording the prior state of every account written from now on.
+    pub fn open_journal(&mut self) {
+        self.journal = Some(BlockUndo::default());
+    }
+
+    /// Stops recording and returns what was recorded since `open_journal`.
+    pub fn close_journal(&mut self) -> BlockUndo {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Puts back the account states recorded in `undo`, removing accounts
+    /// that did not exist before.
+    pub fn restore(&mut self, undo: &BlockUndo) {
+        for (address, prior_account) in undo.prior_accounts.iter() {
+            self.changes
+                .insert(*address, prior_account.clone());
+        }
+    }
+
+    /// Accounts staged so far, `None` for accounts to remove.
+    pub fn changes(&self) -> &HashMap<Address, Option<AccountState>> {
+        &self.changes
+    }
+
+    /// Staged accounts, `None` for accounts to remove.
+    pub fn into_changes(self) -> HashMap<Address, Option<AccountState>> {
+        self.changes
+    }
+
+    fn record_prior_state(&mut self, address: &Address) {
+        let prior_account = self.get(address).cloned();
+        if let Some(journal) = self.journal.as_mut() {
+            journal
+                .prior_accounts
+                .entry(*address)
+                .or_insert(prior_account);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use super::address::Address;
+use super::encoding::Encode;
+use super::hash::StateHash;
+use super::AccountState;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+/// Number of bits in an account key, and so the deepest a path can go.
+const KEY_BITS: usize = 256;
+
+/// Sparse Merkle tree over the accounts, the structure the header
+/// `state_root` commits to. Accounts sit at the path given by the bits of
+/// `StateHash::digest(address)`; a subtree holding a single account is
+/// replaced by that account's leaf and an empty subtree hashes to all
+/// zeros, so the tree only grows as deep as needed to tell keys apart.
+/// Leaves and inner nodes hash under different prefixes.
+///
+/// Accounts with a zero balance, a zero nonce and no multisig policy are
+/// left out: they are indistinguishable from accounts that do not exist,
+/// and nodes create them locally when they are merely looked up.
+///
+/// The hash of every subtree holding two leaves or more is kept, so an
+/// update only rehashes the paths of the accounts it changes, and a root
+/// or a proof reads the untouched subtrees from the cache. Only the leaves
+/// are serialized; the inner nodes are rehashed on deserialization.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<StateHash, StateHash>", into = "BTreeMap<StateHash, StateHash>")]
+pub struct StateTree {
+    leaves: BTreeMap<StateHash, StateHash>,
+    /// Inner node hashes, keyed by depth and the first key of the subtree.
+    nodes: HashMap<(usize, StateHash), StateHash>,
+}
+
+/// Path from the state root to where an account is, or would be, stored.
+/// Proves either the exact state of an account or that it does not exist,
+/// given only a header's `state_root`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountProof {
+    /// Sibling hashes along the path, from the root down.
+    pub siblings: Vec<StateHash>,
+    /// Key and account hash of the leaf that ends the path when it belongs to
+    /// another account, which proves the requested one is absent.
+    pub other_leaf: Option<(StateHash, StateHash)>,
+}
+
+impl StateTree {
+    pub fn from_accounts<'a>(
+        accounts: impl IntoIterator<Item = (&'a Address, &'a AccountState)>,
+    ) -> Self {
+        let leaves: BTreeMap<StateHash, StateHash> = accounts
+            .into_iter()
+            .filter(|(_, account)| !is_empty(account))
+            .map(|(address, account)| (account_key(address), account_hash(account)))
+            .collect();
+        Self::from(leaves)
+    }
+
+    pub fn root(&self) -> StateHash {
+        self.subtree_root(0, &StateHash::default())
+    }
+
+    /// Root of the tree once `changes` are applied, `None` removing an
+    /// account, without modifying it. Only the subtrees holding a changed
+    /// account are rehashed.
+    pub fn root_with(&self, changes: &HashMap<Address, Option<AccountState>>) -> StateHash {
+        let changes: BTreeMap<StateHash, Option<StateHash>> = leaf_changes(changes).collect();
+        self.staged_subtree_root(&changes, 0, &StateHash::default())
+    }
+
+    /// Applies `changes`, `None` removing an account, rehashing the paths
+    /// of the changed accounts only.
+    pub fn update(&mut self, changes: &HashMap<Address, Option<AccountState>>) {
+        for (key, account_hash) in leaf_changes(changes) {
+            for depth in 0..KEY_BITS {
+                self.nodes.remove(&(depth, *subtree_keys(depth, &key).start()));
+            }
+            match account_hash {
+                Some(account_hash) => self.leaves.insert(key, account_hash),
+                None => self.leaves.remove(&key),
+            };
+        }
+        self.hash_subtree(0, StateHash::default());
+    }
+
+    pub fn prove(&self, address: &Address) -> AccountProof {
+        let key = account_key(address);
+        let mut siblings = Vec::new();
+        let mut depth = 0;
+        let other_leaf = loop {
+            let mut leaves = self.leaves.range(subtree_keys(depth, &key));
+            match (leaves.next(), leaves.next()) {
+                (Some(_), Some(_)) => {
+                    siblings.push(self.subtree_root(depth + 1, &flip_bit(&key, depth)));
+                    depth += 1;
+                }
+                (Some((leaf_key, leaf_account_hash)), None) if *leaf_key != key => {
+                    break Some((*leaf_key, *leaf_account_hash));
+                }
+                _ => break None,
+            }
+        };
+        AccountProof {
+            siblings,
+            other_leaf,
+        }
+    }
+
+    /// Hash of the subtree at `depth` on the path of `key`.
+    fn subtree_root(&self, depth: usize, key: &StateHash) -> StateHash {
+        let keys = subtree_keys(depth, key);
+        let mut leaves = self.leaves.range(keys.clone());
+        match (leaves.next(), leaves.next()) {
+            (None, _) => StateHash::default(),
+            (Some((leaf_key, account_hash)), None) => leaf_hash(leaf_key, account_hash),
+            _ => self.nodes[&(depth, *keys.start())],
+        }
+    }
+
+    /// Like `subtree_root` with `changes` applied on top of the leaves.
+    fn staged_subtree_root(
+        &self,
+        changes: &BTreeMap<StateHash, Option<StateHash>>,
+        depth: usize,
+        key: &StateHash,
+    ) -> StateHash {
+        let keys = subtree_keys(depth, key);
+        if changes.range(keys.clone()).next().is_none() {
+            return self.subtree_root(depth, key);
+        }
+        let unchanged_leaves = self
+            .leaves
+            .range(keys.clone())
+            .filter(|(leaf_key, _)| !changes.contains_key(*leaf_key))
+            .map(|(leaf_key, account_hash)| (*leaf_key, *account_hash));
+        let changed_leaves = changes
+            .range(keys.clone())
+            .filter_map(|(leaf_key, account_hash)| Some((*leaf_key, (*account_hash)?)));
+        let mut leaves = unchanged_leaves.chain(changed_leaves);
+        match (leaves.next(), leaves.next()) {
+            (None, _) => StateHash::default(),
+            (Some((leaf_key, account_hash)), None) => leaf_hash(&leaf_key, &account_hash),
+            _ => node_hash(
+                &self.staged_subtree_root(changes, depth + 1, keys.start()),
+                &self.staged_subtree_root(changes, depth + 1, &flip_bit(keys.start(), depth)),
+            ),
+        }
+    }
+
+    /// Hashes the subtree at `depth` on the path of `key`, filling in the
+    /// inner nodes missing from the cache.
+    fn hash_subtree(&mut self, depth: usize, key: StateHash) -> StateHash {
+        let keys = subtree_keys(depth, &key);
+        let mut leaves = self.leaves.range(keys.clone());
+        let (first_leaf, has_second_leaf) = (leaves.next(), leaves.next().is_some());
+        match first_leaf {
+            None => return StateHash::default(),
+            Some((leaf_key, account_hash)) if !has_second_leaf => {
+                return leaf_hash(leaf_key, account_hash);
+            }
+            _ => {}
+        }
+        let node = (depth, *keys.start());
+        if let Some(hash) = self.nodes.get(&node) {
+            return *hash;
+        }
+        let hash = node_hash(
+            &self.hash_subtree(depth + 1, node.1),
+            &self.hash_subtree(depth + 1, flip_bit(&node.1, depth)),
+        );
+        self.nodes.insert(node, hash);
+        hash
+    }
+}
+
+impl From<BTreeMap<StateHash, StateHash>> for StateTree {
+    fn from(leaves: BTreeMap<StateHash, StateHash>) -> Self {
+        let mut tree = Self {
+            leaves,
+            nodes: HashMap::new(),
+        };
+        tree.hash_subtree(0, StateHash::default());
+        tree
+    }
+}
+
+impl From<StateTree> for BTreeMap<StateHash, StateHash> {
+    fn from(tree: StateTree) -> Self {
+        tree.leaves
+    }
+}
+
+impl AccountProof {
+    /// Checks that the account at `address` is `account` in the
+    /// state committed to by `state_root`, `None` meaning it does not exist.
+    pub fn verify(
+        &self,
+        state_root: &StateHash,
+        address: &Address,
+        account: Option<&AccountState>,
+    ) -> bool {
+        if self.siblings.len() >= KEY_BITS {
+            return false;
+        }
+        let key = account_key(address);
+        let account = account.filter(|account| !is_empty(account));
+        let mut hash = match (account, &self.other_leaf) {
+            (Some(account), None) => leaf_hash(&key, &account_hash(account)),
+            (None, None) => StateHash::default(),
+            (None, Some((other_key, other_account_hash))) => {
+                let shares_path = (0..self.siblings.len())
+                    .all(|depth| bit(other_key, depth) == bit(&key, depth));
+                if *other_key == key || !shares_path {
+                    return false;
+                }
+                leaf_hash(other_key, other_account_hash)
+            }
+            (Some(_), Some(_)) => return false,
+        };
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if bit(&key, depth) {
+                node_hash(sibling, &hash)
+            } else {
+                node_hash(&hash, sibling)
+            };
+        }
+        hash == *state_root
+    }
+}
+
+/// Root of the state tree holding `accounts`.
+pub fn state_root(accounts: &HashMap<Address, AccountState>) -> StateHash {
+    StateTree::from_accounts(accounts).root()
+}
+
+/// Leaf key and account hash of every changed account, `None` for accounts
+/// that are removed or left empty.
+fn leaf_changes(
+    changes: &HashMap<Address, Option<AccountState>>,
+) -> impl Iterator<Item = (StateHash, Option<StateHash>)> + '_ {
+    changes.iter().map(|(address, account)| {
+        let account_hash = account
+            .as_ref()
+            .filter(|account| !is_empty(account))
+            .map(account_hash);
+        (account_key(address), account_hash)
+    })
+}
+
+/// First and last keys of the subtree at `depth` on the path of `key`: the
+/// keys sharing its first `depth` bits.
+fn subtree_keys(depth: usize, key: &StateHash) -> RangeInclusive<StateHash> {
+    let mut first = *key.as_bytes();
+    let mut last = first;
+    for (index, (first_byte, last_byte)) in first.iter_mut().zip(last.iter_mut()).enumerate() {
+        let free_bits = (0xffu16 >> depth.saturating_sub(8 * index).min(8)) as u8;
+        *first_byte &= !free_bits;
+        *last_byte |= free_bits;
+    }
+    StateHash::from_bytes(first)..=StateHash::from_bytes(last)
+}
+
+/// `key` with bit `depth` inverted, on the path of the sibling subtree.
+fn flip_bit(key: &StateHash, depth: usize) -> StateHash {
+    let mut bytes = *key.as_bytes();
+    bytes[depth / 8] ^= 0x80 >> (depth % 8);
+    StateHash::from_bytes(bytes)
+}
+
+fn account_key(address: &Address) -> StateHash {
+    StateHash::digest(address.as_bytes())
+}
+
+fn account_hash(account: &AccountState) -> StateHash {
+    StateHash::digest(&account.encode())
+}
+
+fn leaf_hash(key: &StateHash, account_hash: &StateHash) -> StateHash {
+    let mut preimage = vec![LEAF_PREFIX];
+    key.encode_to(&mut preimage);
+    account_hash.encode_to(&mut preimage);
+    StateHash::digest(&preimage)
+}
+
+fn node_hash(left: &StateHash, right: &StateHash) -> StateHash {
+    let mut preimage = vec![NODE_PREFIX];
+    left.encode_to(&mut preimage);
+    right.encode_to(&mut preimage);
+    StateHash::digest(&preimage)
+}
+
+fn is_empty(account: &AccountState) -> bool {
+    account.balance.is_zero() && account.nonce == 0 && account.multisig.is_none()
+}
+
+/// Bit `depth` of `key`, most significant first.
+fn bit(key: &StateHash, depth: usize) -> bool {
+    key.as_bytes()[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
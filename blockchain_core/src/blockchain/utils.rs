@@ -1,22 +1,26 @@
-pub use super::block::Transaction;
+pub use super::block::{SignedTransaction, Transaction};
+use super::encoding::Encode;
+use super::hash::TxHash;
 use k256::{PublicKey};
-use sha256::digest;
 use k256::elliptic_curve::sec1::ToEncodedPoint; 
 
-pub fn hash_transaction(transaction: &Transaction) -> String {
-    digest(convert_transaction_to_string(transaction))
+/// Version tag prepended to the signing payload. Bump it whenever the
+/// payload layout changes so old signatures cannot be reinterpreted.
+pub const TRANSACTION_SIGNING_VERSION: u8 = 4;
+
+/// Hash signed by the sender: covers every transaction field and the chain
+/// id, so a signature is only valid for one nonce on one network.
+pub fn hash_transaction(transaction: &Transaction, chain_id: u64) -> TxHash {
+    TxHash::digest(&transaction_signing_payload(transaction, chain_id))
 }
 
-pub fn convert_transaction_to_string(transaction: &Transaction) -> String {
-    let public_key_from_string = transaction
-        .public_key_from
-        .to_encoded_point(true)
-        .to_string();
-    let public_key_to_string = transaction.public_key_to.to_encoded_point(true).to_string();
-    public_key_from_string
-        + &public_key_to_string
-        + &transaction.amount.to_string()
-        + &transaction.fee.to_string()
+/// `version || chain_id || transaction`, with the chain id as 8 big-endian
+/// bytes and the transaction in its canonical encoding.
+pub fn transaction_signing_payload(transaction: &Transaction, chain_id: u64) -> Vec<u8> {
+    let mut signing_payload = vec![TRANSACTION_SIGNING_VERSION];
+    signing_payload.extend_from_slice(&chain_id.to_be_bytes());
+    transaction.encode_to(&mut signing_payload);
+    signing_payload
 }
 
 pub fn convert_public_key_to_bytes(public_key: &PublicKey) -> Vec<u8> {
@@ -5,34 +5,46 @@ pub mod mock;
 
 #[cfg(test)]
 mod tests {
-    use std::{ops::Add, sync::{Arc, Mutex}, thread, time::Duration};
-
-    use crate::blockchain::{utils::convert_public_key_to_bytes, Blockchain};
-    use crate::mock::mock_miner::{AccountKeys, Miner, Transaction};
+    use std::{collections::HashMap, ops::Add, sync::{Arc, Mutex}, thread, time::Duration};
+
+    use crate::blockchain::{
+        utils::{hash_transaction, transaction_signing_payload},
+        AccountState, Address, AddressError, Asert, BlockError, BlockHash, BlockTiming, Blockchain, ChainSpec,
+        ChainSpecError, DifficultyAdjustment, DifficultyAlgorithm, EmissionSchedule,
+        GenesisAllocation, LightClient, Lwma, MerkleProof, MerkleTree, OrphanPool, PeriodRetarget, PowEngine,
+        RetargetContext, SignatureCache, StateHash, StateTree, StepAdjustment, Decode, DecodeError, Encode,
+        Header, HeaderChain, MultisigPolicy, Recipient, Sender, TimestampError, TransactionError, TxHash, verify_proof,
+        BLOCK_VERSION, MAX_MULTISIG_KEYS,
+    };
+    use crate::mock::mock_miner::{
+        AccountKeys, Block, Coinbase, Miner, SignedTransaction, Transaction,
+    };
     use crate::mock::mock_network::Network;
-    use k256::ecdsa::Signature;
+    use k256::ecdsa::{Signature, SigningKey};
     use primitive_types::U256;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
     #[tokio::test]
     async fn test_mine_one_block() {
         let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
             setup();
+        let genesis_hash = blockchain.genesis_hash();
         let sender_account_public_key = sender_account.get_public_key();
         let receiver_account_public_key = receiver_account.get_public_key();
         let transaction_0: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 0,
         };
         let serialized_transaction_0 = transaction_0.serialize();
 
-        let signature_0: Signature = sender_account.sign_transaction(&transaction_0);
+        let signature_0: Signature = sender_account.sign_transaction(&transaction_0, blockchain.chain_id);
 
         let transaction_1: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 1,
@@ -40,11 +52,11 @@ mod tests {
 
         let serialized_transaction_1 = transaction_1.serialize();
 
-        let signature_1: Signature = sender_account.sign_transaction(&transaction_1);
+        let signature_1: Signature = sender_account.sign_transaction(&transaction_1, blockchain.chain_id);
 
         let transaction_2: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 2,
@@ -52,31 +64,36 @@ mod tests {
 
         let serialized_transaction_2 = transaction_2.serialize();
 
-        let signature_2: Signature = sender_account.sign_transaction(&transaction_2);
+        let signature_2: Signature = sender_account.sign_transaction(&transaction_2, blockchain.chain_id);
         network
             .send_transaction(serialized_transaction_0, &signature_0, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
                 .send_transaction(serialized_transaction_1, &signature_1, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_2, &signature_2, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
 
         miner
-            .compute_next_block(&mut blockchain, String::from(""))
+            .compute_next_block(&mut blockchain, genesis_hash)
             .expect("Block must have been built");
         assert_eq!(sender_account.get_balance(&mut blockchain), U256::from(994));
         assert_eq!(receiver_account.get_balance(&mut blockchain), U256::from(3));
         assert_eq!(
-            blockchain.get_balance(&miner.account_keys.get_public_key()),
-            U256::add(U256::from(3), U256::from(blockchain.mining_reward))
+            blockchain.get_balance(&miner.account_keys.get_address()),
+            U256::add(U256::from(3), blockchain.block_reward_at(1))
         );
     }
 
     #[tokio::test]
     async fn test_chain_reorg() {
         let (mut blockchain, network, miner, sender_account, receiver_account) = setup();
+        // A second node on the same genesis mines the competing branch.
+        let fork_blockchain = blockchain.clone();
         let (new_blockchain, fork_block_hash) = mine_initial_blockchain_helper(
             blockchain,
             miner.clone(),
@@ -90,23 +107,23 @@ mod tests {
         assert_eq!(sender_account_balance, U256::from(988));
         let receiver_account_balance = receiver_account.get_balance(&mut blockchain);
         assert_eq!(receiver_account_balance, U256::from(6));
-        let mut_sender = blockchain
-            .accounts
-            .get_mut(&convert_public_key_to_bytes(
-                &sender_account.get_public_key(),
-            ))
-            .unwrap();
-        mut_sender.nonce = 3;
-        let (forked_blockchain, _) = mine_fork_helper(
-            blockchain,
+        let (fork_blockchain, dominant_block_hash) = mine_fork_helper(
+            fork_blockchain,
+            blockchain.get_block(&fork_block_hash).unwrap().clone(),
             miner,
             sender_account.clone(),
             &receiver_account,
-            fork_block_hash,
             network,
         )
         .await;
-        blockchain = forked_blockchain;
+        let dominant_block = fork_blockchain.get_block(&dominant_block_hash).unwrap().clone();
+        let concurrent_block = fork_blockchain
+            .get_block(&dominant_block.header.prev_hash)
+            .unwrap()
+            .clone();
+        blockchain.add_block(concurrent_block).unwrap();
+        blockchain.add_block(dominant_block).unwrap();
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, dominant_block_hash);
         let receiver_account_balance = receiver_account.get_balance(&mut blockchain);
         assert_eq!(receiver_account_balance, U256::from(28));
         let sender_account_balance = sender_account.get_balance(&mut blockchain);
@@ -114,32 +131,61 @@ mod tests {
     }
 
     fn setup() -> (Blockchain, Network, Miner, AccountKeys, AccountKeys) {
-        let difficulty_divisor: i32 = 20000;
-        let difficulty: U256 = U256::MAX / difficulty_divisor;
-        let target_duration_between_blocks = 5;
-        let max_transactions_per_block = 3;
-        let blocks_between_difficulty_adjustment = 10;
-        let mut blockchain: Blockchain = Blockchain::create_blockchain(
-            difficulty,
-            target_duration_between_blocks,
-            max_transactions_per_block,
-            blocks_between_difficulty_adjustment,
-        );
+        let sender_account = AccountKeys::new();
+        let receiver_account = AccountKeys::new();
+        let chain_spec = test_chain_spec(vec![
+            GenesisAllocation {
+                address: sender_account.get_address(),
+                balance: U256::from(1000),
+            },
+            GenesisAllocation {
+                address: receiver_account.get_address(),
+                balance: U256::zero(),
+            },
+        ]);
+        let mut blockchain = Blockchain::from_spec(&chain_spec).unwrap();
 
         let miner: Miner = Miner::new(&mut blockchain, Network::new());
         let mut network: Network = Network::new();
         network.add_miner(miner.clone());
 
-        let sender_account = AccountKeys::new();
-        let sender_account_public_key = sender_account.get_public_key();
-        blockchain.create_account(&sender_account_public_key);
+        (blockchain, network, miner, sender_account, receiver_account)
+    }
 
-        let receiver_account = AccountKeys::new();
-        let receiver_account_public_key = receiver_account.get_public_key();
-        blockchain.create_account(&receiver_account_public_key);
+    /// Spec of the chain every test runs on, starting now.
+    fn test_chain_spec(allocations: Vec<GenesisAllocation>) -> ChainSpec {
+        ChainSpec {
+            chain_id: 1,
+            address_prefix: String::from("bp"),
+            genesis_timestamp: Blockchain::get_adjusted_time(),
+            initial_difficulty: U256::MAX / 20000,
+            emission: EmissionSchedule::Halving {
+                initial_reward: U256::from(1000),
+                interval: 210_000,
+            },
+            target_duration_between_blocks: 5,
+            max_transactions_per_block: 3,
+            difficulty_algorithm: DifficultyAdjustment::Step(StepAdjustment { interval: 10 }),
+            allocations,
+        }
+    }
+
+    /// A hash no block in the tests has, made of `byte` repeated.
+    fn unknown_hash(byte: u8) -> BlockHash {
+        BlockHash::from_bytes([byte; 32])
+    }
+
+    fn genesis_timestamp(blockchain: &Blockchain) -> u64 {
+        blockchain.get_block(&blockchain.genesis_hash()).unwrap().header.timestamp
+    }
 
-        blockchain.mint(&sender_account_public_key, U256::from(1000));
-        return (blockchain, network, miner, sender_account, receiver_account);
+    /// Grinds the nonce of a hand-built block until it carries the work its
+    /// header claims.
+    fn solve(mut block: Block) -> Block {
+        while !block.header.meets_target() {
+            block.header.nonce += 1;
+        }
+        block
     }
 
     async fn mine_initial_blockchain_helper(
@@ -148,12 +194,12 @@ mod tests {
         mut sender_account: AccountKeys,
         receiver_account: &AccountKeys,
         mut network: Network,
-    ) -> (Blockchain, String) {
+    ) -> (Blockchain, BlockHash) {
         let sender_account_public_key = sender_account.get_public_key();
         let receiver_account_public_key = receiver_account.get_public_key();
         let transaction_0: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 0,
@@ -161,11 +207,11 @@ mod tests {
 
         let serialized_transaction_0 = transaction_0.serialize();
 
-        let signature_0: Signature = sender_account.sign_transaction(&transaction_0);
+        let signature_0: Signature = sender_account.sign_transaction(&transaction_0, blockchain.chain_id);
 
         let transaction_1: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 1,
@@ -173,11 +219,11 @@ mod tests {
 
         let serialized_transaction_1 = transaction_1.serialize();
 
-        let signature_1: Signature = sender_account.sign_transaction(&transaction_1);
+        let signature_1: Signature = sender_account.sign_transaction(&transaction_1, blockchain.chain_id);
 
         let transaction_2: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 2,
@@ -185,25 +231,29 @@ mod tests {
 
         let serialized_transaction_2 = transaction_2.serialize();
 
-        let signature_2: Signature = sender_account.sign_transaction(&transaction_2);
+        let signature_2: Signature = sender_account.sign_transaction(&transaction_2, blockchain.chain_id);
 
         network
             .send_transaction(serialized_transaction_0, &signature_0, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_1, &signature_1, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_2, &signature_2, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
 
+        let genesis_hash = blockchain.genesis_hash();
         let first_block_hash = miner
-            .compute_next_block(&mut blockchain, String::from(""))
+            .compute_next_block(&mut blockchain, genesis_hash)
             .unwrap();
 
         let transaction_3: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 3,
@@ -211,11 +261,11 @@ mod tests {
 
         let serialized_transaction_3 = transaction_3.serialize();
 
-        let signature_3: Signature = sender_account.sign_transaction(&transaction_3);
+        let signature_3: Signature = sender_account.sign_transaction(&transaction_3, blockchain.chain_id);
 
         let transaction_4: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 4,
@@ -223,11 +273,11 @@ mod tests {
 
         let serialized_transaction_4 = transaction_4.serialize();
 
-        let signature_4: Signature = sender_account.sign_transaction(&transaction_4);
+        let signature_4: Signature = sender_account.sign_transaction(&transaction_4, blockchain.chain_id);
 
         let transaction_5: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(1),
             fee: U256::from(1),
             nonce: 5,
@@ -235,37 +285,45 @@ mod tests {
 
         let serialized_transaction_5 = transaction_5.serialize();
 
-        let signature_5: Signature = sender_account.sign_transaction(&transaction_5);
+        let signature_5: Signature = sender_account.sign_transaction(&transaction_5, blockchain.chain_id);
 
         network
             .send_transaction(serialized_transaction_3, &signature_3, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_4, &signature_4, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_5, &signature_5, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
 
         miner
-            .compute_next_block(&mut blockchain, first_block_hash.clone());
-        return (blockchain, first_block_hash);
+            .compute_next_block(&mut blockchain, first_block_hash)
+            .expect("Block must have been built");
+        (blockchain, first_block_hash)
     }
 
+    /// Mines two blocks on top of `fork_block`, which `blockchain` receives
+    /// first, from the transactions with nonces 3 to 7.
     async fn mine_fork_helper(
         mut blockchain: Blockchain,
+        fork_block: Block,
         mut miner: Miner,
         mut sender_account: AccountKeys,
         receiver_account: &AccountKeys,
-        fork_block_hash: String,
         mut network: Network,
-    ) -> (Blockchain, String) {
+    ) -> (Blockchain, BlockHash) {
+        let fork_block_hash = Block::hash_header(&fork_block.header);
+        blockchain.add_block(fork_block).unwrap();
         let sender_account_public_key = sender_account.get_public_key();
         let receiver_account_public_key = receiver_account.get_public_key();
 
         let transaction_3: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(5),
             fee: U256::from(2),
             nonce: 3,
@@ -273,11 +331,11 @@ mod tests {
 
         let serialized_transaction_3 = transaction_3.serialize();
 
-        let signature_3: Signature = sender_account.sign_transaction(&transaction_3);
+        let signature_3: Signature = sender_account.sign_transaction(&transaction_3, blockchain.chain_id);
 
         let transaction_4: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(5),
             fee: U256::from(2),
             nonce: 4,
@@ -285,11 +343,11 @@ mod tests {
 
         let serialized_transaction_4 = transaction_4.serialize();
 
-        let signature_4: Signature = sender_account.sign_transaction(&transaction_4);
+        let signature_4: Signature = sender_account.sign_transaction(&transaction_4, blockchain.chain_id);
 
         let transaction_5: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(5),
             fee: U256::from(2),
             nonce: 5,
@@ -297,30 +355,28 @@ mod tests {
 
         let serialized_transaction_5 = transaction_5.serialize();
 
-        let signature_5: Signature = sender_account.sign_transaction(&transaction_5);
+        let signature_5: Signature = sender_account.sign_transaction(&transaction_5, blockchain.chain_id);
 
         network
             .send_transaction(serialized_transaction_3, &signature_3, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_4, &signature_4, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_5, &signature_5, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
 
         let concurrent_block_hash = miner
             .compute_next_block(&mut blockchain, fork_block_hash)
             .unwrap();
 
-        let mut_sender = blockchain
-            .accounts
-            .get_mut(&convert_public_key_to_bytes(&sender_account_public_key))
-            .unwrap();
-        mut_sender.nonce = 6;
         let transaction_6: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(5),
             fee: U256::from(2),
             nonce: 6,
@@ -328,11 +384,11 @@ mod tests {
 
         let serialized_transaction_6 = transaction_6.serialize();
 
-        let signature_6: Signature = sender_account.sign_transaction(&transaction_6);
+        let signature_6: Signature = sender_account.sign_transaction(&transaction_6, blockchain.chain_id);
 
         let transaction_7: Transaction = Transaction {
-            public_key_from: sender_account_public_key,
-            public_key_to: receiver_account_public_key,
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
             amount: U256::from(5),
             fee: U256::from(2),
             nonce: 7,
@@ -340,20 +396,22 @@ mod tests {
 
         let serialized_transaction_7 = transaction_7.serialize();
 
-        let signature_7: Signature = sender_account.sign_transaction(&transaction_7);
+        let signature_7: Signature = sender_account.sign_transaction(&transaction_7, blockchain.chain_id);
 
         network
             .send_transaction(serialized_transaction_6, &signature_6, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
         network
             .send_transaction(serialized_transaction_7, &signature_7, &mut miner, &mut blockchain)
-            .await;
+            .await
+            .unwrap();
 
         let dominant_block_hash = miner
             .compute_next_block(&mut blockchain, concurrent_block_hash)
             .unwrap();
 
-        return (blockchain, dominant_block_hash);
+        (blockchain, dominant_block_hash)
     }
 
     #[tokio::test]
@@ -373,18 +431,14 @@ mod tests {
         network.add_miner(miner1.clone());
         network.add_miner(miner2.clone());
 
-        // Use the same accounts in both blockchain states.
+        // Both nodes share the genesis allocations of the sender and the receiver.
         let sender_pub = sender_account.get_public_key();
         let receiver_pub = receiver_account.get_public_key();
-        blockchain1.create_account(&sender_pub);
-        blockchain1.create_account(&receiver_pub);
-        blockchain1.mint(&sender_pub, U256::from(1000));
-        // Assume blockchain2 is identical to blockchain1 initially.
 
         // Create a transaction: sender sends 10 tokens (fee 1) to receiver.
         let transaction: Transaction = Transaction {
-            public_key_from: sender_pub,
-            public_key_to: receiver_pub,
+            from: Sender::Key(sender_pub),
+            to: Recipient::Account(Address::from_public_key(&receiver_pub)),
             amount: U256::from(10),
             fee: U256::from(1),
             nonce: 0,
@@ -392,15 +446,16 @@ mod tests {
 
         let serialized_transaction = transaction.serialize();
 
-        let signature: Signature = sender_account.sign_transaction(&transaction);
+        let signature: Signature = sender_account.sign_transaction(&transaction, blockchain1.chain_id);
 
         // Add the transaction to miner1's mempool (which is part of blockchain1).
         network
             .send_transaction(serialized_transaction, &signature, &mut miner1, &mut blockchain1)
-            .await;
+            .await
+            .unwrap();
 
         // Miner1 mines a block on its blockchain.
-        let parent_hash = blockchain1.current_longest_chain_latest_block_hash.clone();
+        let parent_hash = blockchain1.current_longest_chain_latest_block_hash;
         let new_block_hash = miner1
             .compute_next_block(&mut blockchain1, parent_hash)
             .expect("Block must be mined");
@@ -424,23 +479,39 @@ mod tests {
 
         // Verify that receiver's balance is updated in blockchain2.
         // In this transaction, receiver should receive 10 tokens.
-        assert_eq!(blockchain2.get_balance(&receiver_pub), U256::from(10));
+        assert_eq!(blockchain2.get_balance(&Address::from_public_key(&receiver_pub)), U256::from(10));
+
+        // The producer recorded in the block is credited on both nodes, not the receiving miner.
+        let expected_miner_balance = blockchain2.block_reward_at(1) + U256::from(1);
+        assert_eq!(
+            blockchain1.get_balance(&miner1.account_keys.get_address()),
+            expected_miner_balance
+        );
+        assert_eq!(
+            blockchain2.get_balance(&miner1.account_keys.get_address()),
+            expected_miner_balance
+        );
+        assert_eq!(
+            blockchain2.get_balance(&miner2.account_keys.get_address()),
+            U256::zero()
+        );
     }
 
     #[tokio::test]
     async fn test_send_blockchain_multithreading() {
         let (blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
         let thread_safe_blockchain = Arc::new(Mutex::new(blockchain));
         let miner_chain_reference = Arc::clone(&thread_safe_blockchain);
 
         thread::spawn(move || {
-            let mut hash = String::from("");
+            let mut hash = genesis_hash;
             
             loop {           
                 {
                 let mut locked_miner_chain = miner_chain_reference.lock().expect("Lock to be acquired");
                 println!("Lock acquired by miner");
-                hash = miner.compute_next_block(&mut *locked_miner_chain, hash).expect("Next block to be computed"); 
+                hash = miner.compute_next_block(&mut locked_miner_chain, hash).expect("Next block to be computed"); 
                 }
                 thread::yield_now();
             }   
@@ -460,4 +531,2388 @@ mod tests {
         thread::sleep(Duration::from_secs(1));
     }
     }
+
+    #[tokio::test]
+    async fn test_block_with_forged_signature_is_rejected() {
+        let (mut blockchain, _, miner, sender_account, mut receiver_account) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let sender_account_public_key = sender_account.get_public_key();
+        let receiver_account_public_key = receiver_account.get_public_key();
+        let transaction: Transaction = Transaction {
+            from: Sender::Key(sender_account_public_key),
+            to: Recipient::Account(Address::from_public_key(&receiver_account_public_key)),
+            amount: U256::from(500),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+
+        // The receiver signs a transaction spending from the sender's account.
+        let forged_signature: Signature = receiver_account.sign_transaction(&transaction, blockchain.chain_id);
+        let forged_transaction = SignedTransaction::new(transaction, forged_signature);
+        assert!(!forged_transaction.verify_signature(blockchain.chain_id));
+
+        let coinbase = Coinbase::new(
+            miner.account_keys.get_address(),
+            blockchain.block_reward_at(1),
+            std::slice::from_ref(&forged_transaction),
+        );
+        let block = Block::create_block(
+            1,
+            genesis_timestamp(&blockchain) + 1,
+            genesis_hash,
+            1,
+            blockchain.get_next_difficulty(&genesis_hash),
+            &[forged_transaction],
+            coinbase,
+        );
+        // Header checks run first, so an invalid header is reported before
+        // any signature is verified.
+        let mut stale_block = block.clone();
+        stale_block.header.timestamp = 0;
+        assert!(matches!(
+            blockchain.add_block(solve(stale_block)),
+            Err(BlockError::BadTimestamp(TimestampError::NotAfterMedianTimePast { .. }))
+        ));
+        assert_eq!(
+            blockchain.add_block(solve(block)),
+            Err(BlockError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadSignature
+            })
+        );
+        assert_eq!(blockchain.get_balance(&Address::from_public_key(&sender_account_public_key)), U256::from(1000));
+        assert_eq!(blockchain.get_balance(&Address::from_public_key(&receiver_account_public_key)), U256::zero());
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, genesis_hash);
+    }
+
+    #[tokio::test]
+    async fn test_signature_replay_across_nonces_and_chains_is_rejected() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let transaction: Transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+        let signature: Signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        let signed_transaction = SignedTransaction::new(transaction.clone(), signature);
+        assert!(signed_transaction.verify_signature(blockchain.chain_id));
+
+        // Same signature attached to the next nonce.
+        let mut replayed_transaction = transaction.clone();
+        replayed_transaction.nonce = 1;
+        assert!(!SignedTransaction::new(replayed_transaction, signature)
+            .verify_signature(blockchain.chain_id));
+
+        // Same transaction and signature submitted on another network.
+        let other_chain_id = blockchain.chain_id + 1;
+        assert!(!signed_transaction.verify_signature(other_chain_id));
+        blockchain.chain_id = other_chain_id;
+        assert_eq!(
+            network
+                .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+                .await,
+            Err(TransactionError::BadSignature)
+        );
+        assert!(miner.mempool.is_empty());
+    }
+
+    #[test]
+    fn test_transaction_signing_payload_vector() {
+        let sender_account =
+            AccountKeys::from_signing_key(SigningKey::from_bytes(&[1u8; 32].into()).unwrap());
+        let receiver_account =
+            AccountKeys::from_signing_key(SigningKey::from_bytes(&[2u8; 32].into()).unwrap());
+        let transaction: Transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 7,
+        };
+
+        let signing_payload = transaction_signing_payload(&transaction, 1);
+        // Sender and recipient each take a tag byte; amount 10 and fee 1 each
+        // take a length byte and one value byte.
+        assert_eq!(signing_payload.len(), 1 + 8 + 34 + 21 + 2 + 2 + 16);
+        assert_eq!(signing_payload[0], 4);
+        assert_eq!(signing_payload[1..9], 1u64.to_be_bytes());
+        assert_eq!(signing_payload[signing_payload.len() - 16..], 7u128.to_be_bytes());
+
+        assert_eq!(
+            hash_transaction(&transaction, 1).to_string(),
+            "9c351cdd2e676a7f0f0b8d33db3c94655802f628b20343f3084fd3e5bca3a7ac"
+        );
+        assert_eq!(
+            hash_transaction(&transaction, 2).to_string(),
+            "bb188914014272357cca78802ee79165cd053c77f01449261d44f33045073d13"
+        );
+        let mut next_nonce_transaction = transaction.clone();
+        next_nonce_transaction.nonce = 8;
+        assert_eq!(
+            hash_transaction(&next_nonce_transaction, 1).to_string(),
+            "ec73cfd5e136162e87b2d58fde0034894f74db69c7cee94c4fb40debe7ea263d"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_with_inflated_coinbase_is_rejected() {
+        let (mut blockchain, _, miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let miner_address = miner.account_keys.get_address();
+        let block = solve(Block::create_block(
+            1,
+            genesis_timestamp(&blockchain) + 1,
+            genesis_hash,
+            1,
+            blockchain.get_next_difficulty(&genesis_hash),
+            &[],
+            Coinbase::new(miner_address, blockchain.block_reward_at(1) + U256::from(1), &[]),
+        ));
+        assert_eq!(blockchain.add_block(block), Err(BlockError::BadCoinbase));
+
+        let mut block = Block::create_block(
+            1,
+            genesis_timestamp(&blockchain) + 1,
+            genesis_hash,
+            1,
+            blockchain.get_next_difficulty(&genesis_hash),
+            &[],
+            Coinbase::new(miner_address, blockchain.block_reward_at(1), &[]),
+        );
+        block.header.coinbase.fees = U256::from(1);
+        assert_eq!(blockchain.add_block(solve(block)), Err(BlockError::BadCoinbase));
+        assert_eq!(blockchain.get_balance(&miner_address), U256::zero());
+    }
+
+    #[tokio::test]
+    async fn test_header_commits_to_target_height_and_version() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let first_block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let second_block_hash = miner
+            .compute_next_block(&mut blockchain, first_block_hash)
+            .expect("Block must have been built");
+        let second_block = blockchain.get_block(&second_block_hash).unwrap().clone();
+        assert_eq!(second_block.header.height, 2);
+        assert_eq!(second_block.header.difficulty, blockchain.get_next_difficulty(&genesis_hash));
+
+        let mut easier_target_block = second_block.clone();
+        easier_target_block.header.difficulty = U256::MAX;
+        let mut wrong_height_block = second_block.clone();
+        wrong_height_block.header.height = 5;
+        let mut wrong_version_block = second_block.clone();
+        wrong_version_block.header.version += 1;
+        let expected_errors = [
+            BlockError::BadTarget {
+                expected: blockchain.get_next_difficulty(&genesis_hash),
+                found: U256::MAX,
+            },
+            BlockError::BadHeight {
+                expected: 2,
+                found: 5,
+            },
+            BlockError::BadVersion { version: BLOCK_VERSION + 1 },
+        ];
+        for (tampered_block, expected_error) in [easier_target_block, wrong_height_block, wrong_version_block]
+            .into_iter()
+            .zip(expected_errors)
+        {
+            assert_ne!(
+                Block::hash_header(&tampered_block.header),
+                Block::hash_header(&second_block.header)
+            );
+            assert_eq!(
+                blockchain.validate_header(&tampered_block.header),
+                Err(expected_error.clone())
+            );
+            assert_eq!(blockchain.add_block(tampered_block), Err(expected_error));
+        }
+
+        // A block whose claimed target differs from the one its parent requires is rejected.
+        let mut harder_target_block = second_block.clone();
+        harder_target_block.header.difficulty = blockchain.get_next_difficulty(&genesis_hash) / 2;
+        assert!(matches!(
+            blockchain.validate_header(&harder_target_block.header),
+            Err(BlockError::BadTarget { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_short_high_difficulty_chain_beats_long_low_difficulty_chain() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let miner_address = miner.account_keys.get_address();
+        let low_difficulty_target = blockchain.get_next_difficulty(&genesis_hash);
+        let fork_block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+
+        let mut long_chain_tip = fork_block_hash;
+        for _ in 0..3 {
+            long_chain_tip = miner
+                .compute_next_block(&mut blockchain, long_chain_tip)
+                .expect("Block must have been built");
+        }
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, long_chain_tip);
+        assert_eq!(blockchain.get_balance(&miner_address), blockchain.block_reward_at(1) * 4);
+
+        // Targets derive from the ancestry, so the short branch gets its harder
+        // target from a retarget: two blocks far faster than an hour apart
+        // divide it by the maximum of 4.
+        blockchain.difficulty_algorithm = DifficultyAdjustment::PeriodRetarget(PeriodRetarget { interval: 2 });
+        blockchain.target_duration_between_blocks = 3600;
+        let short_chain_tip = miner
+            .compute_next_block(&mut blockchain, fork_block_hash)
+            .expect("Block must have been built");
+        assert_eq!(
+            blockchain.get_block(&short_chain_tip).unwrap().header.difficulty,
+            low_difficulty_target / 4
+        );
+        // One block at a 4x harder target outweighs three blocks at the easy target.
+        assert!(
+            blockchain.hash_to_cumulative_work[&short_chain_tip]
+                > blockchain.hash_to_cumulative_work[&long_chain_tip]
+        );
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, short_chain_tip);
+        assert_eq!(blockchain.get_balance(&miner_address), blockchain.block_reward_at(1) * 2);
+    }
+
+    #[test]
+    fn test_header_work_grows_as_target_shrinks() {
+        let mut header = Block::create_block(
+            1,
+            0,
+            BlockHash::GENESIS_PARENT,
+            0,
+            U256::MAX,
+            &[],
+            Coinbase::new(AccountKeys::new().get_address(), U256::zero(), &[]),
+        )
+        .header;
+        assert_eq!(header.work(), U256::one());
+        header.difficulty = U256::MAX >> 1;
+        assert_eq!(header.work(), U256::from(2));
+        header.difficulty = (U256::one() << 240) - 1;
+        assert_eq!(header.work(), U256::one() << 16);
+        header.difficulty = U256::zero();
+        assert_eq!(header.work(), U256::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_difficulty_is_derived_from_branch_ancestry() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        blockchain.difficulty_algorithm = DifficultyAdjustment::Step(StepAdjustment { interval: 3 });
+        // Long enough that the test blocks are always faster, however loaded the machine is.
+        blockchain.target_duration_between_blocks = 3600;
+        let initial_target = blockchain.get_next_difficulty(&genesis_hash);
+        let mut base_chain = blockchain.clone();
+
+        let fork_block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let mut first_branch = vec![fork_block_hash];
+        for _ in 0..3 {
+            let tip = *first_branch.last().unwrap();
+            first_branch.push(miner.compute_next_block(&mut blockchain, tip).unwrap());
+        }
+        // A different producer makes sure the second branch does not reproduce the first one.
+        let mut second_miner = Miner::new(&mut blockchain, Network::new());
+        let mut second_branch = vec![fork_block_hash];
+        for _ in 0..2 {
+            let tip = *second_branch.last().unwrap();
+            second_branch.push(second_miner.compute_next_block(&mut blockchain, tip).unwrap());
+        }
+        assert_ne!(first_branch[1], second_branch[1]);
+
+        // Blocks mined within the same few seconds retarget to a harder target at height 3.
+        let retargeted_block = blockchain.get_block(&first_branch[2]).unwrap();
+        assert_eq!(retargeted_block.header.height, 3);
+        assert_eq!(retargeted_block.header.difficulty, initial_target - initial_target / 50);
+        assert_eq!(
+            blockchain.get_next_difficulty(&second_branch[2]),
+            initial_target - initial_target / 50
+        );
+
+        // A node seeing the second branch first computes the same targets.
+        for hash in second_branch.iter().chain(first_branch[1..].iter()) {
+            let block = blockchain.get_block(hash).unwrap().clone();
+            assert_eq!(base_chain.add_block(block), Ok(()));
+        }
+        for hash in first_branch.iter().chain(second_branch.iter()) {
+            assert_eq!(
+                base_chain.get_next_difficulty(hash),
+                blockchain.get_next_difficulty(hash)
+            );
+        }
+        assert_eq!(
+            base_chain.current_longest_chain_latest_block_hash,
+            blockchain.current_longest_chain_latest_block_hash
+        );
+    }
+
+    fn block_timings(timestamps: &[u64], target: U256) -> Vec<BlockTiming> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(height, timestamp)| BlockTiming {
+                height: height as u64,
+                timestamp: *timestamp,
+                target,
+            })
+            .collect()
+    }
+
+    fn retarget(
+        algorithm: &dyn DifficultyAlgorithm,
+        ancestors: &[BlockTiming],
+        anchor: Option<BlockTiming>,
+        target_duration_between_blocks: u64,
+    ) -> U256 {
+        algorithm.next_target(&RetargetContext {
+            ancestors,
+            anchor,
+            target_duration_between_blocks,
+        })
+    }
+
+    #[test]
+    fn test_step_adjustment_moves_target_by_two_percent() {
+        let target = U256::one() << 200;
+        let step = StepAdjustment { interval: 4 };
+        let fast_blocks = block_timings(&[0, 1, 2, 3], target);
+        assert_eq!(retarget(&step, &fast_blocks, None, 5), target - target / 50);
+        let slow_blocks = block_timings(&[0, 10, 20, 30], target);
+        assert_eq!(retarget(&step, &slow_blocks, None, 5), target + target / 50);
+        let on_time_blocks = block_timings(&[0, 5, 10, 15], target);
+        assert_eq!(retarget(&step, &on_time_blocks, None, 5), target);
+        // Only every `interval` blocks.
+        assert_eq!(retarget(&step, &fast_blocks[..3], None, 5), target);
+    }
+
+    #[test]
+    fn test_period_retarget_is_clamped_to_four_times() {
+        let target = U256::one() << 200;
+        let period_retarget = PeriodRetarget { interval: 4 };
+        let on_time_blocks = block_timings(&[0, 4, 8, 12], target);
+        assert_eq!(retarget(&period_retarget, &on_time_blocks, None, 4), target);
+        let twice_too_slow_blocks = block_timings(&[0, 8, 16, 24], target);
+        assert_eq!(retarget(&period_retarget, &twice_too_slow_blocks, None, 4), target * 2);
+        let very_slow_blocks = block_timings(&[0, 100, 200, 300], target);
+        assert_eq!(retarget(&period_retarget, &very_slow_blocks, None, 4), target * 4);
+        let instant_blocks = block_timings(&[0, 0, 0, 0], target);
+        assert_eq!(retarget(&period_retarget, &instant_blocks, None, 4), target / 4);
+    }
+
+    #[test]
+    fn test_lwma_follows_weighted_solve_times() {
+        let target = U256::one() << 200;
+        let lwma = Lwma { window: 4 };
+        assert_eq!(lwma.window(), 5);
+        let on_time_blocks = block_timings(&[0, 5, 10, 15, 20], target);
+        assert_eq!(retarget(&lwma, &on_time_blocks, None, 5), target);
+        let twice_too_fast_blocks = block_timings(&[0, 5, 10, 15, 20].map(|t| t / 2), target);
+        assert!(retarget(&lwma, &twice_too_fast_blocks, None, 5) < target * 6 / 10);
+        // A recent slow block weighs more than an old one.
+        let recently_slow_blocks = block_timings(&[0, 5, 10, 15, 45], target);
+        let early_slow_blocks = block_timings(&[0, 30, 35, 40, 45], target);
+        assert!(
+            retarget(&lwma, &recently_slow_blocks, None, 5)
+                > retarget(&lwma, &early_slow_blocks, None, 5)
+        );
+    }
+
+    #[test]
+    fn test_asert_doubles_target_per_half_life_behind_schedule() {
+        let target = U256::one() << 200;
+        let asert = Asert { half_life: 3600 };
+        let anchor = BlockTiming { height: 0, timestamp: 1_000, target };
+        let parent = |timestamp: u64| BlockTiming { height: 10, timestamp, target };
+        let on_schedule = [parent(1_000 + 10 * 5)];
+        assert_eq!(retarget(&asert, &on_schedule, Some(anchor), 5), target);
+        let one_half_life_behind = [parent(1_000 + 10 * 5 + 3600)];
+        assert_eq!(retarget(&asert, &one_half_life_behind, Some(anchor), 5), target * 2);
+        let one_half_life_ahead = [BlockTiming { height: 10 + 3600 / 5, ..parent(1_000 + 10 * 5) }];
+        assert_eq!(retarget(&asert, &one_half_life_ahead, Some(anchor), 5), target / 2);
+        let half_a_half_life_behind = [parent(1_000 + 10 * 5 + 1800)];
+        let expected = target * 14142 / 10000;
+        let next_target = retarget(&asert, &half_a_half_life_behind, Some(anchor), 5);
+        assert!(next_target > expected - expected / 1000 && next_target < expected + expected / 1000);
+    }
+
+    #[tokio::test]
+    async fn test_blocks_are_mined_against_selected_difficulty_algorithm() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        blockchain.difficulty_algorithm = DifficultyAdjustment::Lwma(Lwma { window: 2 });
+        // Solve times are clamped to at least one second, which keeps the target stable here.
+        blockchain.target_duration_between_blocks = 1;
+        let mut tip = genesis_hash;
+        for _ in 0..4 {
+            let expected_target = blockchain.get_next_difficulty(&tip);
+            tip = miner
+                .compute_next_block(&mut blockchain, tip)
+                .expect("Block must have been built");
+            assert_eq!(blockchain.get_block(&tip).unwrap().header.difficulty, expected_target);
+        }
+        assert_eq!(blockchain.get_block(&tip).unwrap().header.height, 4);
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, tip);
+    }
+
+    #[tokio::test]
+    async fn test_asert_is_anchored_at_first_block_not_genesis() {
+        let mut chain_spec = test_chain_spec(vec![]);
+        // The genesis timestamp of a spec usually predates the chain by far.
+        chain_spec.genesis_timestamp -= 30 * 24 * 60 * 60;
+        chain_spec.difficulty_algorithm = DifficultyAdjustment::Asert(Asert { half_life: 3600 });
+        let mut blockchain = Blockchain::from_spec(&chain_spec).unwrap();
+        let mut light_client = LightClient::from_spec(&chain_spec).unwrap();
+        let mut miner = Miner::new(&mut blockchain, Network::new());
+        let initial_target = chain_spec.initial_difficulty;
+
+        let mut tip = blockchain.genesis_hash();
+        for _ in 0..3 {
+            tip = miner
+                .compute_next_block(&mut blockchain, tip)
+                .expect("Block must have been built");
+            light_client
+                .add_header(blockchain.get_block(&tip).unwrap().header.clone())
+                .unwrap();
+        }
+        let target_at = |height| blockchain.get_block_by_height(height).unwrap().header.difficulty;
+        assert_eq!(target_at(1), initial_target);
+        assert_eq!(target_at(2), initial_target);
+        // Mined a few seconds ahead of schedule, not a month behind it.
+        assert!(target_at(3) > initial_target - initial_target / 100);
+        assert!(target_at(3) < initial_target + initial_target / 100);
+        assert_eq!(Block::hash_header(light_client.tip()), tip);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_consensus_rules() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        blockchain.median_time_past_window = 3;
+        let mut tip = genesis_hash;
+        for _ in 0..3 {
+            tip = miner
+                .compute_next_block(&mut blockchain, tip)
+                .expect("Block must have been built");
+        }
+        let tip_block = blockchain.get_block(&tip).unwrap().clone();
+        let median_time_past = blockchain.get_median_time_past(&tip).unwrap();
+        assert!(tip_block.header.timestamp >= median_time_past);
+
+        let child_header = |timestamp: u64| {
+            let mut header = tip_block.header.clone();
+            header.prev_hash = tip;
+            header.height += 1;
+            header.timestamp = timestamp;
+            header
+        };
+        assert_eq!(
+            blockchain.validate_timestamp(&child_header(median_time_past)),
+            Err(TimestampError::NotAfterMedianTimePast {
+                timestamp: median_time_past,
+                median_time_past
+            })
+        );
+        assert!(matches!(
+            blockchain.validate_timestamp(&child_header(0)),
+            Err(TimestampError::NotAfterMedianTimePast { .. })
+        ));
+        assert_eq!(
+            blockchain.validate_timestamp(&child_header(median_time_past + 1)),
+            Ok(())
+        );
+
+        let far_future_timestamp =
+            Blockchain::get_adjusted_time() + blockchain.max_future_block_time + 60;
+        assert!(matches!(
+            blockchain.validate_timestamp(&child_header(far_future_timestamp)),
+            Err(TimestampError::TooFarInFuture { .. })
+        ));
+
+        // A mined block with a backdated timestamp is rejected before it reaches the chain.
+        let mut backdated_block = tip_block.clone();
+        backdated_block.header = child_header(median_time_past);
+        assert!(matches!(
+            blockchain.add_block(backdated_block),
+            Err(BlockError::BadTimestamp(TimestampError::NotAfterMedianTimePast { .. }))
+        ));
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, tip);
+    }
+
+    #[tokio::test]
+    async fn test_rejections_report_typed_errors() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let miner_address = miner.account_keys.get_address();
+
+        // Height and target are only checked against the parent once it is known.
+        let orphan_block = solve(Block::create_block(
+            1,
+            0,
+            unknown_hash(0xff),
+            7,
+            blockchain.get_next_difficulty(&genesis_hash),
+            &[],
+            Coinbase::new(miner_address, blockchain.block_reward_at(1), &[]),
+        ));
+        assert_eq!(
+            blockchain.add_block(orphan_block),
+            Err(BlockError::UnknownParent {
+                prev_hash: unknown_hash(0xff)
+            })
+        );
+
+        let overspending_transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(2000),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+        let signature =
+            sender_account.sign_transaction(&overspending_transaction, blockchain.chain_id);
+        assert_eq!(
+            network
+                .send_transaction(overspending_transaction.serialize(), &signature, &mut miner, &mut blockchain)
+                .await,
+            Err(TransactionError::InsufficientBalance {
+                balance: U256::from(1000),
+                required: U256::from(2001)
+            })
+        );
+
+        // A block skipping a nonce passes validation but cannot be applied, so it is not kept.
+        let future_nonce_transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(1),
+            fee: U256::from(1),
+            nonce: 1,
+        };
+        let signature =
+            sender_account.sign_transaction(&future_nonce_transaction, blockchain.chain_id);
+        let signed_transaction = SignedTransaction::new(future_nonce_transaction, signature);
+        let block = solve(Block::create_block(
+            1,
+            genesis_timestamp(&blockchain) + 1,
+            genesis_hash,
+            1,
+            blockchain.get_next_difficulty(&genesis_hash),
+            std::slice::from_ref(&signed_transaction),
+            Coinbase::new(
+                miner_address,
+                blockchain.block_reward_at(1),
+                std::slice::from_ref(&signed_transaction),
+            ),
+        ));
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+        assert_eq!(
+            blockchain.add_block(block.clone()),
+            Err(BlockError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadNonce {
+                    expected: 0,
+                    found: 1
+                }
+            })
+        );
+        assert!(blockchain.get_block(&Block::hash_header(&block.header)).is_none());
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, genesis_hash);
+
+        let block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let block = blockchain.get_block(&block_hash).unwrap().clone();
+        assert_eq!(blockchain.add_block(block), Err(BlockError::AlreadyKnown));
+    }
+
+    #[tokio::test]
+    async fn test_failed_block_application_leaves_state_unchanged() {
+        let (mut blockchain, _, mut miner, mut sender_account, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let miner_address = miner.account_keys.get_address();
+        let sender_public_key = sender_account.get_public_key();
+        let fresh_receiver_public_key = AccountKeys::new().get_public_key();
+        let (chain_id, difficulty, mining_reward) =
+            (blockchain.chain_id, blockchain.get_next_difficulty(&genesis_hash), blockchain.block_reward_at(1));
+        let timestamp = genesis_timestamp(&blockchain) + 1;
+        let mut sign = |amount: u64, nonce: u128| {
+            let transaction = Transaction {
+                from: Sender::Key(sender_public_key),
+                to: Recipient::Account(Address::from_public_key(&fresh_receiver_public_key)),
+                amount: U256::from(amount),
+                fee: U256::from(1),
+                nonce,
+            };
+            let signature = sender_account.sign_transaction(&transaction, chain_id);
+            SignedTransaction::new(transaction, signature)
+        };
+        let block_with = |transactions: &[SignedTransaction], prev_hash: BlockHash, height: u64| {
+            Block::create_block(
+                1,
+                timestamp,
+                prev_hash,
+                height,
+                difficulty,
+                transactions,
+                Coinbase::new(miner_address, mining_reward, transactions),
+            )
+        };
+
+        // The first transaction is fine on its own, the second one overspends.
+        let partially_valid_block = solve(block_with(
+            &[sign(10, 0), sign(2000, 1)],
+            genesis_hash,
+            1,
+        ));
+        let partially_valid_block_hash = Block::hash_header(&partially_valid_block.header);
+        let accounts_before = blockchain.accounts.clone();
+        assert_eq!(
+            blockchain.add_block(partially_valid_block.clone()),
+            Err(BlockError::InvalidTransaction {
+                index: 1,
+                error: TransactionError::InsufficientBalance {
+                    balance: U256::from(989),
+                    required: U256::from(2001)
+                }
+            })
+        );
+        assert_eq!(blockchain.accounts, accounts_before);
+        assert!(blockchain.invalid_blocks.contains(&partially_valid_block_hash));
+        assert_eq!(
+            blockchain.add_block(partially_valid_block),
+            Err(BlockError::KnownInvalid)
+        );
+
+        // Receivers that have never been seen are created when the block applies.
+        let mut valid_block = block_with(&[sign(10, 0)], genesis_hash, 1);
+        valid_block.header.state_root = blockchain.compute_state_root(&valid_block).unwrap();
+        let valid_block = solve(valid_block);
+        let valid_block_hash = Block::hash_header(&valid_block.header);
+        assert_eq!(blockchain.add_block(valid_block), Ok(()));
+        assert_eq!(blockchain.get_balance(&Address::from_public_key(&fresh_receiver_public_key)), U256::from(10));
+        assert_eq!(blockchain.get_balance(&Address::from_public_key(&sender_public_key)), U256::from(989));
+
+        // An equally heavy sibling is stored without being applied. A producer
+        // cannot build on it, since the state it leads to cannot be computed.
+        let mut bad_nonce_block = block_with(&[sign(20, 5)], genesis_hash, 1);
+        bad_nonce_block.header.timestamp += 1;
+        let bad_nonce_block = solve(bad_nonce_block);
+        let bad_nonce_block_hash = Block::hash_header(&bad_nonce_block.header);
+        assert_eq!(blockchain.add_block(bad_nonce_block), Ok(()));
+        let accounts_before = blockchain.accounts.clone();
+        assert!(matches!(
+            miner.compute_next_block(&mut blockchain, bad_nonce_block_hash),
+            Err(BlockError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadNonce { .. }
+            })
+        ));
+        assert_eq!(blockchain.accounts, accounts_before);
+
+        // Once it is extended, the reorg fails on it and the original branch is kept.
+        let mut rejected_child = block_with(&[], bad_nonce_block_hash, 2);
+        rejected_child.header.timestamp += 2;
+        let rejected_child = solve(rejected_child);
+        let rejected_child_hash = Block::hash_header(&rejected_child.header);
+        assert!(matches!(
+            blockchain.add_block(rejected_child),
+            Err(BlockError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadNonce { .. }
+            })
+        ));
+        assert_eq!(blockchain.accounts, accounts_before);
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, valid_block_hash);
+        assert!(blockchain.invalid_blocks.contains(&bad_nonce_block_hash));
+        assert!(blockchain.invalid_blocks.contains(&rejected_child_hash));
+
+        let grandchild_block = block_with(&[], rejected_child_hash, 3);
+        assert_eq!(
+            blockchain.add_block(grandchild_block),
+            Err(BlockError::InvalidAncestor {
+                hash: rejected_child_hash
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_blocks_wait_in_orphan_pool() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut receiving_chain = blockchain.clone();
+        let mut tip = genesis_hash;
+        let mut mined_hashes = vec![];
+        for _ in 0..3 {
+            tip = miner
+                .compute_next_block(&mut blockchain, tip)
+                .expect("Block must have been built");
+            mined_hashes.push(tip);
+        }
+
+        for hash in mined_hashes[1..].iter().rev() {
+            let block = blockchain.get_block(hash).unwrap().clone();
+            assert!(matches!(
+                receiving_chain.add_block(block.clone()),
+                Err(BlockError::UnknownParent { .. })
+            ));
+            assert_eq!(receiving_chain.add_block(block), Err(BlockError::AlreadyKnown));
+        }
+        assert_eq!(receiving_chain.orphans.len(), 2);
+        assert_eq!(receiving_chain.get_missing_ancestors(), vec![mined_hashes[0]]);
+        assert_eq!(receiving_chain.current_longest_chain_latest_block_hash, genesis_hash);
+
+        let first_block = blockchain.get_block(&mined_hashes[0]).unwrap().clone();
+        assert_eq!(receiving_chain.add_block(first_block), Ok(()));
+        assert!(receiving_chain.orphans.is_empty());
+        assert!(receiving_chain.get_missing_ancestors().is_empty());
+        assert_eq!(receiving_chain.current_longest_chain_latest_block_hash, tip);
+        assert_eq!(
+            receiving_chain.get_balance(&miner.account_keys.get_address()),
+            blockchain.block_reward_at(1) * 3
+        );
+
+        // Orphans must still carry the work their header claims.
+        let mut unworked_block = blockchain.get_block(&mined_hashes[2]).unwrap().clone();
+        unworked_block.header.prev_hash = unknown_hash(0xff);
+        while unworked_block.header.meets_target() {
+            unworked_block.header.nonce += 1;
+        }
+        assert!(matches!(
+            receiving_chain.add_block(unworked_block),
+            Err(BlockError::InsufficientWork { .. })
+        ));
+        // Nor may they claim a target any hash meets.
+        let mut free_block = blockchain.get_block(&mined_hashes[2]).unwrap().clone();
+        free_block.header.prev_hash = unknown_hash(0xfe);
+        free_block.header.difficulty = U256::MAX;
+        assert!(free_block.header.meets_target());
+        assert_eq!(
+            receiving_chain.add_block(free_block),
+            Err(BlockError::OrphanTargetTooEasy {
+                max_target: receiving_chain.get_max_orphan_target(),
+                found: U256::MAX,
+            })
+        );
+        assert!(receiving_chain.orphans.is_empty());
+    }
+
+    #[test]
+    fn test_orphan_pool_limits_size_and_age() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let orphan_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let orphan = blockchain.get_block(&orphan_hash).unwrap().clone();
+        let orphan_with_parent = |parent: BlockHash| {
+            let mut block = orphan.clone();
+            block.header.prev_hash = parent;
+            (Block::hash_header(&block.header), block)
+        };
+
+        let mut pool = OrphanPool::new(2, 60);
+        for (received_at, parent) in [(0, 0x01), (10, 0x02), (20, 0x03)] {
+            let (hash, block) = orphan_with_parent(unknown_hash(parent));
+            pool.insert(hash, block, received_at);
+        }
+        assert_eq!(pool.len(), 2);
+        let mut missing_ancestors = pool.missing_ancestors();
+        missing_ancestors.sort();
+        assert_eq!(missing_ancestors, vec![unknown_hash(0x02), unknown_hash(0x03)]);
+
+        pool.expire(75);
+        assert_eq!(pool.missing_ancestors(), vec![unknown_hash(0x03)]);
+        pool.expire(81);
+        assert!(pool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reorg_restores_exact_state_from_undo_records() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut second_miner = Miner::new(&mut blockchain, Network::new());
+        let fresh_receiver_public_key = AccountKeys::new().get_public_key();
+        let accounts_before = blockchain.accounts.clone();
+
+        let transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(Address::from_public_key(&fresh_receiver_public_key)),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let first_branch_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        assert!(blockchain.get_account(&Address::from_public_key(&fresh_receiver_public_key)).is_some());
+        assert_eq!(
+            blockchain.block_undo[&first_branch_hash]
+                .prior_accounts
+                .get(&Address::from_public_key(&fresh_receiver_public_key)),
+            Some(&None)
+        );
+
+        let second_branch_base_hash = second_miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, first_branch_hash);
+        let second_branch_tip_hash = second_miner
+            .compute_next_block(&mut blockchain, second_branch_base_hash)
+            .expect("Block must have been built");
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, second_branch_tip_hash);
+        assert_eq!(
+            blockchain.get_fork_branches(&second_branch_tip_hash, &first_branch_hash),
+            (
+                vec![second_branch_tip_hash, second_branch_base_hash],
+                vec![first_branch_hash]
+            )
+        );
+
+        // Undoing the first branch removes the receiver it created and gives
+        // back the sender's balance and nonce exactly.
+        let mut expected_accounts = accounts_before;
+        expected_accounts
+            .get_mut(&second_miner.account_keys.get_address())
+            .unwrap()
+            .balance = blockchain.block_reward_at(1) * 2;
+        assert_eq!(blockchain.accounts, expected_accounts);
+        let fresh_receiver_address = Address::from_public_key(&fresh_receiver_public_key);
+        assert!(blockchain.get_account(&fresh_receiver_address).is_none());
+        // The state tree was rolled back with the accounts.
+        assert!(blockchain
+            .prove_account(&fresh_receiver_address)
+            .verify(&blockchain.tip().header.state_root, &fresh_receiver_address, None));
+        let mut undo_hashes: Vec<&BlockHash> = blockchain.block_undo.keys().collect();
+        undo_hashes.sort();
+        let mut canonical_hashes = vec![&second_branch_base_hash, &second_branch_tip_hash];
+        canonical_hashes.sort();
+        assert_eq!(undo_hashes, canonical_hashes);
+    }
+
+    #[tokio::test]
+    async fn test_height_index_follows_reorgs() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut second_miner = Miner::new(&mut blockchain, Network::new());
+        let hash_of = |block: Option<&Block>| block.map(|block| Block::hash_header(&block.header));
+        assert_eq!(Block::hash_header(&blockchain.tip().header), genesis_hash);
+        assert_eq!(blockchain.canonical_chain, vec![genesis_hash]);
+
+        let base_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let first_branch_hash = miner
+            .compute_next_block(&mut blockchain, base_hash)
+            .expect("Block must have been built");
+        let second_branch_hash = second_miner
+            .compute_next_block(&mut blockchain, base_hash)
+            .expect("Block must have been built");
+
+        assert_eq!(Block::hash_header(&blockchain.tip().header), first_branch_hash);
+        assert_eq!(hash_of(blockchain.get_block_by_height(2)), Some(first_branch_hash));
+        assert!(blockchain.is_canonical(&first_branch_hash));
+        assert!(!blockchain.is_canonical(&second_branch_hash));
+        assert_eq!(hash_of(blockchain.ancestor(&second_branch_hash, 1)), Some(base_hash));
+
+        let second_branch_tip_hash = second_miner
+            .compute_next_block(&mut blockchain, second_branch_hash)
+            .expect("Block must have been built");
+        assert_eq!(
+            blockchain.canonical_chain,
+            vec![genesis_hash, base_hash, second_branch_hash, second_branch_tip_hash]
+        );
+        assert_eq!(blockchain.tip().header.height, 3);
+        assert!(!blockchain.is_canonical(&first_branch_hash));
+        assert!(blockchain.is_canonical(&second_branch_hash));
+        assert!(blockchain.get_block_by_height(4).is_none());
+
+        let canonical_hashes: Vec<BlockHash> = blockchain
+            .canonical_iter(0, 10)
+            .map(|block| Block::hash_header(&block.header))
+            .collect();
+        assert_eq!(canonical_hashes, blockchain.canonical_chain);
+        assert_eq!(
+            hash_of(blockchain.canonical_iter(2, 2).next()),
+            Some(second_branch_hash)
+        );
+        assert_eq!(blockchain.canonical_iter(3, 2).count(), 0);
+        assert_eq!(blockchain.canonical_iter(4, 10).count(), 0);
+
+        assert_eq!(hash_of(blockchain.ancestor(&second_branch_tip_hash, 0)), Some(second_branch_tip_hash));
+        assert_eq!(hash_of(blockchain.ancestor(&second_branch_tip_hash, 2)), Some(base_hash));
+        assert_eq!(hash_of(blockchain.ancestor(&first_branch_hash, 1)), Some(base_hash));
+        assert_eq!(hash_of(blockchain.ancestor(&base_hash, 1)), Some(genesis_hash));
+        assert!(blockchain.ancestor(&genesis_hash, 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_block_hashes_are_32_bytes_and_share_one_pow_check() {
+        let (mut blockchain, _, mut miner, _, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let block = blockchain.get_block(&block_hash).unwrap().clone();
+
+        assert_eq!(block.header.prev_hash, genesis_hash);
+        assert_eq!(block_hash.to_string().len(), 64);
+        assert_eq!(block_hash.to_string().parse::<BlockHash>(), Ok(block_hash));
+        assert!("00ff".parse::<BlockHash>().is_err());
+        assert!("zz".repeat(32).parse::<BlockHash>().is_err());
+        let serialized_hash = serde_json::to_string(&block_hash).unwrap();
+        assert_eq!(serialized_hash, format!("\"{}\"", block_hash));
+        assert_eq!(serde_json::from_str::<BlockHash>(&serialized_hash).unwrap(), block_hash);
+
+        // The miner stopped on a header the validator accepts, and the
+        // validator reads the hash the same way: big-endian over 32 bytes.
+        assert!(block.header.meets_target());
+        assert!(block_hash.to_u256() <= block.header.difficulty);
+        let mut unworked_block = block.clone();
+        unworked_block.header.prev_hash = block_hash;
+        unworked_block.header.height = 2;
+        unworked_block.header.timestamp += 1;
+        while unworked_block.header.meets_target() {
+            unworked_block.header.nonce += 1;
+        }
+        let unworked_block_hash = Block::hash_header(&unworked_block.header);
+        assert!(matches!(
+            blockchain.validate_block(&unworked_block),
+            Err(BlockError::InsufficientWork { hash, .. }) if hash == unworked_block_hash
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_binary_encoding_round_trips() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(300),
+            fee: U256::zero(),
+            nonce: 0,
+        };
+        let encoded_transaction = transaction.encode();
+        // A tagged compressed key, a tagged address, 300 in two bytes, a zero
+        // fee and a 16-byte nonce.
+        assert_eq!(encoded_transaction.len(), 34 + 21 + 3 + 1 + 16);
+        assert_eq!(encoded_transaction[55..59], [2, 0x01, 0x2c, 0]);
+        assert_eq!(Transaction::decode(&encoded_transaction), Ok(transaction.clone()));
+
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        let signed_transaction = SignedTransaction::new(transaction.clone(), signature);
+        assert_eq!(
+            SignedTransaction::decode(&signed_transaction.encode()),
+            Ok(signed_transaction)
+        );
+
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let block = blockchain.get_block(&block_hash).unwrap().clone();
+        let decoded_header = Header::decode(&block.header.encode()).unwrap();
+        assert_eq!(Block::hash_header(&decoded_header), block_hash);
+        let decoded_block = Block::decode(&block.encode()).unwrap();
+        assert_eq!(Block::hash_header(&decoded_block.header), block_hash);
+        assert_eq!(decoded_block.transactions, block.transactions);
+
+        let blocks = vec![block.clone(), block];
+        let decoded_blocks = Vec::<Block>::decode(&blocks.encode()).unwrap();
+        assert_eq!(decoded_blocks.len(), 2);
+        assert_eq!(decoded_blocks[1].transactions, blocks[1].transactions);
+    }
+
+    #[test]
+    fn test_binary_decoding_is_canonical() {
+        assert_eq!(U256::zero().encode(), vec![0]);
+        assert_eq!(U256::from(0x1234).encode(), vec![2, 0x12, 0x34]);
+        assert_eq!(U256::MAX.encode().len(), 33);
+        assert_eq!(U256::decode(&[2, 0x12, 0x34]), Ok(U256::from(0x1234)));
+        assert_eq!(U256::decode(&[2, 0x00, 0x34]), Err(DecodeError::NonCanonicalInteger));
+        assert_eq!(U256::decode(&[1, 0x00]), Err(DecodeError::NonCanonicalInteger));
+        assert_eq!(U256::decode(&[33]), Err(DecodeError::NonCanonicalInteger));
+        assert_eq!(U256::decode(&[2, 0x12]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(U256::decode(&[1, 0x12, 0x00]), Err(DecodeError::TrailingBytes(1)));
+
+        // Lengths below 0xfd must use the single-byte form.
+        assert_eq!(vec![7u8; 0xfc].encode()[0], 0xfc);
+        assert_eq!(vec![7u8; 0xfd].encode()[..3], [0xfd, 0x00, 0xfd]);
+        assert_eq!(Vec::<u8>::decode(&[0xfd, 0x00, 0x01, 7]), Err(DecodeError::NonCanonicalLength));
+        assert_eq!(Vec::<u8>::decode(&[0xfd, 0x01, 0x00]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(Vec::<u8>::decode(&[1, 7]), Ok(vec![7]));
+
+        let transaction = Transaction {
+            from: Sender::Key(AccountKeys::new().get_public_key()),
+            to: Recipient::Account(AccountKeys::new().get_address()),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 3,
+        };
+        let encoded_transaction = transaction.encode();
+        let mut invalid_key = encoded_transaction.clone();
+        invalid_key[1] = 0x04;
+        assert_eq!(Transaction::decode(&invalid_key), Err(DecodeError::InvalidPublicKey));
+        let mut unknown_sender = encoded_transaction.clone();
+        unknown_sender[0] = 2;
+        assert_eq!(Transaction::decode(&unknown_sender), Err(DecodeError::UnknownTag(2)));
+        let mut trailing = encoded_transaction.clone();
+        trailing.push(0);
+        assert_eq!(Transaction::decode(&trailing), Err(DecodeError::TrailingBytes(1)));
+        for length in 0..encoded_transaction.len() {
+            assert!(Transaction::decode(&encoded_transaction[..length]).is_err());
+        }
+        assert_eq!(
+            SignedTransaction::decode(&[encoded_transaction, vec![1], vec![0; 64]].concat()),
+            Err(DecodeError::InvalidSignature)
+        );
+    }
+
+    /// Decodes `bytes` with every decoder. None may panic, and whatever
+    /// decodes must re-encode to the exact same bytes.
+    fn decode_with_every_decoder(bytes: &[u8]) {
+        if let Ok(transaction) = Transaction::deseralize(bytes) {
+            assert_eq!(transaction.encode(), bytes);
+        }
+        if let Ok(signed_transaction) = SignedTransaction::deseralize(bytes) {
+            assert_eq!(signed_transaction.encode(), bytes);
+        }
+        if let Ok(header) = Header::decode(bytes) {
+            assert_eq!(header.encode(), bytes);
+        }
+        if let Ok(block) = Block::decode(bytes) {
+            assert_eq!(block.encode(), bytes);
+        }
+        if let Ok(blocks) = Vec::<Block>::decode(bytes) {
+            assert_eq!(blocks.encode(), bytes);
+        }
+        if let Ok(hash) = BlockHash::decode(bytes) {
+            assert_eq!(hash.encode(), bytes);
+        }
+        if let Ok(value) = U256::decode(bytes) {
+            assert_eq!(value.encode(), bytes);
+        }
+        if let Ok(string) = String::decode(bytes) {
+            assert_eq!(string.encode(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_decoders_survive_random_bytes() {
+        let mut rng = StdRng::seed_from_u64(16);
+        for _ in 0..5000 {
+            let mut bytes = vec![0u8; rng.gen_range(0..512)];
+            rng.fill(&mut bytes[..]);
+            decode_with_every_decoder(&bytes);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decoders_survive_mutated_blocks() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let first_block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let encoded_block = blockchain.get_block(&first_block_hash).unwrap().encode();
+
+        // Corrupted copies of a real block go through decoding and
+        // validation without panicking on the way.
+        let mut rng = StdRng::seed_from_u64(16);
+        for _ in 0..2000 {
+            let mut bytes = encoded_block.clone();
+            match rng.gen_range(0..3) {
+                0 => {
+                    let index = rng.gen_range(0..bytes.len());
+                    bytes[index] ^= 1 << rng.gen_range(0..8);
+                }
+                1 => bytes.truncate(rng.gen_range(0..bytes.len())),
+                _ => bytes.insert(rng.gen_range(0..=bytes.len()), rng.gen()),
+            }
+            decode_with_every_decoder(&bytes);
+            if let Ok(block) = Block::decode(&bytes) {
+                let _ = blockchain.validate_block(&block);
+            }
+        }
+
+        let mut garbage_transaction = vec![0u8; 40];
+        rng.fill(&mut garbage_transaction[..]);
+        let mut block = blockchain.get_block(&first_block_hash).unwrap().clone();
+        block.header.prev_hash = first_block_hash;
+        block.header.height = 2;
+        block.header.timestamp += 1;
+        block.header.difficulty = blockchain.get_next_difficulty(&first_block_hash);
+        block.transactions.push(garbage_transaction.clone());
+        let block = solve(block);
+        assert!(matches!(
+            blockchain.add_block(block.clone()),
+            Err(BlockError::InvalidTransaction {
+                index: 1,
+                error: TransactionError::Malformed(_),
+            })
+        ));
+        assert!(matches!(
+            miner.on_block_receive(block, &mut blockchain),
+            Err(BlockError::InvalidTransaction { .. } | BlockError::KnownInvalid)
+        ));
+        assert!(matches!(
+            miner
+                .on_transaction_receive(garbage_transaction, &signature, &mut blockchain)
+                .await,
+            Err(TransactionError::Malformed(_))
+        ));
+
+        // Amounts that would overflow are rejected instead of panicking.
+        let overflowing_transaction = Transaction {
+            amount: U256::MAX,
+            nonce: 1,
+            ..transaction
+        };
+        let signature = sender_account.sign_transaction(&overflowing_transaction, blockchain.chain_id);
+        assert!(matches!(
+            miner
+                .on_transaction_receive(overflowing_transaction.serialize(), &signature, &mut blockchain)
+                .await,
+            Err(TransactionError::InsufficientBalance { required, .. }) if required == U256::MAX
+        ));
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, first_block_hash);
+    }
+
+    #[tokio::test]
+    async fn test_chain_spec_derives_one_genesis_per_spec() {
+        let (mut blockchain, _, mut miner, sender_account, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let genesis_block = blockchain.get_block(&genesis_hash).unwrap().clone();
+        assert_eq!(genesis_block.header.height, 0);
+        assert!(genesis_block.header.prev_hash.is_genesis_parent());
+        assert_eq!(blockchain.canonical_chain, vec![genesis_hash]);
+        assert_eq!(sender_account.get_balance(&mut blockchain), U256::from(1000));
+
+        let spec = ChainSpec {
+            chain_id: blockchain.chain_id,
+            address_prefix: blockchain.address_prefix.clone(),
+            genesis_timestamp: genesis_block.header.timestamp,
+            initial_difficulty: genesis_block.header.difficulty,
+            emission: blockchain.emission.clone(),
+            target_duration_between_blocks: blockchain.target_duration_between_blocks,
+            max_transactions_per_block: blockchain.max_transactions_per_block,
+            difficulty_algorithm: blockchain.difficulty_algorithm.clone(),
+            allocations: vec![GenesisAllocation {
+                address: sender_account.get_address(),
+                balance: U256::from(1000),
+            }],
+        };
+        let loaded_spec = ChainSpec::from_json(&spec.to_json()).unwrap();
+        assert_eq!(loaded_spec, spec);
+        // Allocations are written as bech32m addresses of the spec's network,
+        // so a typo fails the checksum instead of funding an unknown account.
+        let address_text = blockchain.format_address(&sender_account.get_address());
+        assert!(spec.to_json().contains(&address_text));
+        let mut mistyped_address_text = address_text.clone();
+        let last_character = mistyped_address_text.pop().unwrap();
+        mistyped_address_text.push(if last_character == 'q' { 'p' } else { 'q' });
+        assert!(matches!(
+            ChainSpec::from_json(&spec.to_json().replacen(&address_text, &mistyped_address_text, 1)),
+            Err(ChainSpecError::Parse(_))
+        ));
+        let shipped_spec =
+            ChainSpec::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../node/chain_spec.json")).unwrap();
+        assert_eq!(shipped_spec.allocations.len(), 1);
+        assert_eq!(loaded_spec.genesis_hash(), spec.genesis_hash());
+        assert_eq!(
+            Blockchain::from_spec(&loaded_spec).unwrap().genesis_hash(),
+            spec.genesis_hash()
+        );
+
+        // Every field of the spec, allocations included, feeds the genesis hash.
+        let mut richer_spec = spec.clone();
+        richer_spec.allocations[0].balance += U256::one();
+        let mut other_chain_spec = spec.clone();
+        other_chain_spec.chain_id += 1;
+        for changed_spec in [&richer_spec, &other_chain_spec] {
+            assert_ne!(changed_spec.genesis_hash(), spec.genesis_hash());
+        }
+
+        // Blocks of another chain never attach, and no second genesis is accepted.
+        let mut foreign_chain = Blockchain::from_spec(&richer_spec).unwrap();
+        let foreign_block_hash = miner
+            .compute_next_block(&mut foreign_chain, richer_spec.genesis_hash())
+            .expect("Block must have been built");
+        let foreign_block = foreign_chain.get_block(&foreign_block_hash).unwrap().clone();
+        assert!(matches!(
+            blockchain.add_block(foreign_block),
+            Err(BlockError::UnknownParent { prev_hash }) if prev_hash == richer_spec.genesis_hash()
+        ));
+        let foreign_genesis = richer_spec.genesis_block();
+        assert_eq!(
+            blockchain.add_block(foreign_genesis),
+            Err(BlockError::GenesisMismatch {
+                hash: richer_spec.genesis_hash()
+            })
+        );
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, genesis_hash);
+
+        let mut double_allocation_spec = spec.clone();
+        double_allocation_spec
+            .allocations
+            .push(double_allocation_spec.allocations[0].clone());
+        let mut zero_difficulty_spec = spec.clone();
+        zero_difficulty_spec.initial_difficulty = U256::zero();
+        let mut unbounded_emission_spec = spec.clone();
+        unbounded_emission_spec.emission = EmissionSchedule::Halving {
+            initial_reward: U256::MAX,
+            interval: 1,
+        };
+        for invalid_spec in [double_allocation_spec, zero_difficulty_spec, unbounded_emission_spec] {
+            assert!(matches!(
+                Blockchain::from_spec(&invalid_spec),
+                Err(ChainSpecError::Invalid(_))
+            ));
+            assert!(matches!(
+                ChainSpec::from_json(&invalid_spec.to_json()),
+                Err(ChainSpecError::Invalid(_))
+            ));
+        }
+        assert!(matches!(
+            ChainSpec::from_json(&spec.to_json().replacen("chain_id", "chain", 1)),
+            Err(ChainSpecError::Parse(_))
+        ));
+        assert!(matches!(
+            ChainSpec::load("no/such/chain_spec.json"),
+            Err(ChainSpecError::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_block_over_transaction_limit_is_rejected() {
+        let (mut blockchain, _, miner, sender_account, mut receiver_account) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let max_transactions = blockchain.max_transactions_per_block;
+        // Signed by the wrong key: the limit is enforced before any signature is verified.
+        let transactions: Vec<SignedTransaction> = (0..=max_transactions as u128)
+            .map(|nonce| {
+                let transaction = Transaction {
+                    from: Sender::Key(sender_account.get_public_key()),
+                    to: Recipient::Account(receiver_account.get_address()),
+                    amount: U256::from(10),
+                    fee: U256::from(1),
+                    nonce,
+                };
+                let signature = receiver_account.sign_transaction(&transaction, blockchain.chain_id);
+                SignedTransaction::new(transaction, signature)
+            })
+            .collect();
+        let block_with = |transactions: &[SignedTransaction]| {
+            solve(Block::create_block(
+                1,
+                genesis_timestamp(&blockchain) + 1,
+                genesis_hash,
+                1,
+                blockchain.get_next_difficulty(&genesis_hash),
+                transactions,
+                Coinbase::new(
+                    miner.account_keys.get_address(),
+                    blockchain.block_reward_at(1),
+                    transactions,
+                ),
+            ))
+        };
+        let oversized_block = block_with(&transactions);
+        let full_block = block_with(&transactions[..max_transactions]);
+
+        assert_eq!(
+            blockchain.add_block(oversized_block),
+            Err(BlockError::TooManyTransactions {
+                max: max_transactions,
+                found: max_transactions + 1
+            })
+        );
+        assert!(matches!(
+            blockchain.add_block(full_block),
+            Err(BlockError::InvalidTransaction { index: 0, error: TransactionError::BadSignature })
+        ));
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, genesis_hash);
+    }
+
+    #[test]
+    fn test_emission_schedules() {
+        let halving = EmissionSchedule::Halving {
+            initial_reward: U256::from(1000),
+            interval: 2,
+        };
+        let rewards: Vec<U256> = (0..=5).map(|height| halving.reward_at(height)).collect();
+        assert_eq!(rewards, [0, 1000, 1000, 500, 500, 250].map(U256::from));
+        assert_eq!(halving.issued_through(5), U256::from(3250));
+        assert_eq!(halving.reward_at(2 * 256 + 1), U256::zero());
+        assert!(halving.issued_through(u64::MAX) <= halving.max_issuance().unwrap());
+
+        let smooth_decay = EmissionSchedule::SmoothDecay {
+            max_supply: U256::from(1_000_000),
+            decay: 4,
+        };
+        assert_eq!(smooth_decay.reward_at(1), U256::from(250_000));
+        assert_eq!(smooth_decay.reward_at(2), U256::from(187_500));
+        for schedule in [&halving, &smooth_decay] {
+            let mut issued = U256::zero();
+            for height in 0..100 {
+                issued += schedule.reward_at(height);
+                assert_eq!(schedule.issued_through(height), issued);
+            }
+        }
+        assert!(smooth_decay.reward_at(100).is_zero());
+        assert!(smooth_decay.issued_through(u64::MAX) <= U256::from(1_000_000));
+    }
+
+    #[tokio::test]
+    async fn test_issuance_follows_emission_schedule_across_reorgs() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        blockchain.emission = EmissionSchedule::Halving {
+            initial_reward: U256::from(1000),
+            interval: 2,
+        };
+        let mut second_miner = Miner::new(&mut blockchain, Network::new());
+        let total_balance = |blockchain: &Blockchain| {
+            blockchain
+                .accounts
+                .values()
+                .fold(U256::zero(), |total, account| total + account.balance)
+        };
+        assert_eq!(blockchain.total_supply_at(0), U256::from(1000));
+        assert_eq!(total_balance(&blockchain), blockchain.total_supply_at(0));
+
+        // Fees move coins without minting any.
+        let transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(10),
+            fee: U256::from(5),
+            nonce: 0,
+        };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let fork_block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let mut first_branch_tip = fork_block_hash;
+        for _ in 0..2 {
+            first_branch_tip = miner
+                .compute_next_block(&mut blockchain, first_branch_tip)
+                .expect("Block must have been built");
+        }
+        let first_branch_tip_block = blockchain.get_block(&first_branch_tip).unwrap().clone();
+        assert_eq!(first_branch_tip_block.header.coinbase.reward, U256::from(500));
+        assert_eq!(total_balance(&blockchain), blockchain.total_supply_at(3));
+        assert_eq!(blockchain.total_supply_at(3), U256::from(1000 + 2500));
+
+        let mut second_branch_tip = fork_block_hash;
+        for _ in 0..3 {
+            second_branch_tip = second_miner
+                .compute_next_block(&mut blockchain, second_branch_tip)
+                .expect("Block must have been built");
+        }
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, second_branch_tip);
+        assert_eq!(total_balance(&blockchain), blockchain.total_supply_at(4));
+        assert_eq!(
+            blockchain.get_balance(&miner.account_keys.get_address()),
+            U256::from(1000 + 5)
+        );
+        assert_eq!(
+            blockchain.get_balance(&second_miner.account_keys.get_address()),
+            U256::from(1000 + 500 + 500)
+        );
+
+        // A coinbase still claiming the pre-halving reward is rejected.
+        let mut overpaying_block = first_branch_tip_block;
+        overpaying_block.header.prev_hash = second_branch_tip;
+        overpaying_block.header.height = 5;
+        overpaying_block.header.coinbase.reward = U256::from(500);
+        assert_eq!(blockchain.block_reward_at(5), U256::from(250));
+        assert_eq!(
+            blockchain.validate_coinbase(&overpaying_block),
+            Err(BlockError::BadCoinbase)
+        );
+    }
+
+    #[test]
+    fn test_smooth_decay_rewards_stay_cheap_at_large_heights() {
+        let schedule = EmissionSchedule::SmoothDecay {
+            max_supply: U256::MAX,
+            decay: 1_000_000,
+        };
+        let height = 1_000_000;
+        let mut issued = U256::zero();
+        for height in 1..=height {
+            issued += schedule.reward_after(height, issued);
+        }
+        assert_eq!(schedule.issued_through(height), issued);
+        assert_eq!(schedule.reward_after(height + 1, issued), schedule.reward_at(height + 1));
+        assert!(!schedule.reward_after(height + 1, issued).is_zero());
+
+        // Full and light chains keep the issuance per block instead of
+        // replaying the schedule from genesis for every block.
+        let mut spec = test_chain_spec(vec![GenesisAllocation {
+            address: AccountKeys::new().get_address(),
+            balance: U256::from(1000),
+        }]);
+        spec.initial_difficulty = U256::MAX;
+        spec.emission = EmissionSchedule::SmoothDecay {
+            max_supply: U256::from(10).pow(U256::from(30)),
+            decay: 1000,
+        };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        let mut light_client = LightClient::from_spec(&spec).unwrap();
+        let miner_address = AccountKeys::new().get_address();
+        let chain_height = 1000;
+        let mut tip = blockchain.genesis_hash();
+        for height in 1..=chain_height {
+            let mut block = Block::create_block(
+                0,
+                spec.genesis_timestamp + height,
+                tip,
+                height,
+                blockchain.get_next_difficulty(&tip),
+                &[],
+                Coinbase::new(miner_address, blockchain.block_reward_at(height), &[]),
+            );
+            block.header.state_root = blockchain.compute_state_root(&block).unwrap();
+            let block = solve(block);
+            tip = Block::hash_header(&block.header);
+            light_client.add_header(block.header.clone()).unwrap();
+            blockchain.add_block(block).unwrap();
+        }
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, tip);
+        assert_eq!(Block::hash_header(light_client.tip()), tip);
+        assert_eq!(
+            blockchain.total_supply_at(chain_height),
+            U256::from(1000) + spec.emission.issued_through(chain_height)
+        );
+        assert_eq!(blockchain.get_balance(&miner_address), spec.emission.issued_through(chain_height));
+        assert_eq!(
+            blockchain.block_reward_at(chain_height + 1),
+            spec.emission.reward_at(chain_height + 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_headers_commit_to_account_state() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, _) = setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut diverged_chain = blockchain.clone();
+        let fresh_receiver_public_key = AccountKeys::new().get_public_key();
+        let transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(Address::from_public_key(&fresh_receiver_public_key)),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let block = blockchain.get_block(&block_hash).unwrap().clone();
+        let genesis_state_root = blockchain.get_block(&genesis_hash).unwrap().header.state_root;
+        assert_eq!(block.header.state_root, StateTree::from_accounts(&blockchain.accounts).root());
+        assert_ne!(block.header.state_root, genesis_state_root);
+
+        // Proofs check against a header alone, for present and absent accounts.
+        let sender_address = sender_account.get_address();
+        let sender_state = blockchain.get_account(&sender_account.get_address()).unwrap().clone();
+        let sender_proof = blockchain.prove_account(&sender_account.get_address());
+        assert!(sender_proof.verify(&block.header.state_root, &sender_address, Some(&sender_state)));
+        let stale_sender_state = AccountState {
+            balance: U256::from(1000),
+            nonce: 0,
+            multisig: None,
+        };
+        assert!(!sender_proof.verify(&block.header.state_root, &sender_address, Some(&stale_sender_state)));
+        assert!(!sender_proof.verify(&block.header.state_root, &sender_address, None));
+        assert!(!sender_proof.verify(&genesis_state_root, &sender_address, Some(&sender_state)));
+
+        // Looking an account up creates it locally, which must not change the root.
+        let unknown_public_key = AccountKeys::new().get_public_key();
+        assert_eq!(blockchain.get_balance(&Address::from_public_key(&unknown_public_key)), U256::zero());
+        assert_eq!(block.header.state_root, StateTree::from_accounts(&blockchain.accounts).root());
+        let unknown_address = Address::from_public_key(&unknown_public_key);
+        let absence_proof = blockchain.prove_account(&Address::from_public_key(&unknown_public_key));
+        assert!(absence_proof.verify(&block.header.state_root, &unknown_address, None));
+        assert!(!absence_proof.verify(&block.header.state_root, &unknown_address, Some(&sender_state)));
+
+        // A node whose accounts drifted from the chain rejects the block.
+        diverged_chain
+            .accounts
+            .get_mut(&sender_address)
+            .unwrap()
+            .balance += U256::one();
+        assert!(matches!(
+            diverged_chain.add_block(block.clone()),
+            Err(BlockError::BadStateRoot { found, .. }) if found == block.header.state_root
+        ));
+        assert_eq!(diverged_chain.current_longest_chain_latest_block_hash, genesis_hash);
+
+        // So does every node when the producer commits to the wrong state.
+        let accounts_before = blockchain.accounts.clone();
+        let mut misreported_block = Block::create_block(
+            1,
+            block.header.timestamp + 1,
+            block_hash,
+            2,
+            blockchain.get_next_difficulty(&block_hash),
+            &[],
+            Coinbase::new(miner.account_keys.get_address(), blockchain.block_reward_at(2), &[]),
+        );
+        misreported_block.header.state_root = block.header.state_root;
+        assert!(matches!(
+            blockchain.add_block(solve(misreported_block)),
+            Err(BlockError::BadStateRoot { .. })
+        ));
+        assert_eq!(blockchain.accounts, accounts_before);
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, block_hash);
+    }
+
+    #[test]
+    fn test_account_proofs_cover_every_account() {
+        let accounts: HashMap<Address, AccountState> = (1..=40u64)
+            .map(|index| {
+                (
+                    AccountKeys::new().get_address(),
+                    AccountState {
+                        balance: U256::from(index),
+                        nonce: u128::from(index % 3),
+                        multisig: None,
+                    },
+                )
+            })
+            .collect();
+        let tree = StateTree::from_accounts(&accounts);
+        let state_root = tree.root();
+        assert_eq!(StateTree::default().root(), StateHash::default());
+        for (address, account) in accounts.iter() {
+            let proof = tree.prove(address);
+            assert!(proof.other_leaf.is_none());
+            assert!(proof.verify(&state_root, address, Some(account)));
+            let mut richer_account = account.clone();
+            richer_account.balance += U256::one();
+            assert!(!proof.verify(&state_root, address, Some(&richer_account)));
+        }
+        for _ in 0..40 {
+            let address = AccountKeys::new().get_address();
+            let proof = tree.prove(&address);
+            assert!(proof.verify(&state_root, &address, None));
+            let mut truncated_proof = proof.clone();
+            if truncated_proof.siblings.pop().is_some() {
+                assert!(!truncated_proof.verify(&state_root, &address, None));
+            }
+        }
+
+        // The root depends on the accounts only, not on the order they were inserted in.
+        let mut sorted_accounts: Vec<(&Address, &AccountState)> = accounts.iter().collect();
+        sorted_accounts.sort_by_key(|(address, _)| *address);
+        assert_eq!(StateTree::from_accounts(sorted_accounts).root(), state_root);
+
+        // Updating the tree in place gives the root of a tree rebuilt from
+        // the updated accounts, whether accounts change, appear or go.
+        let mut changes: HashMap<Address, Option<AccountState>> = accounts
+            .iter()
+            .take(20)
+            .enumerate()
+            .map(|(index, (address, account))| {
+                let changed_account = match index % 3 {
+                    0 => None,
+                    1 => Some(AccountState::default()),
+                    _ => Some(AccountState {
+                        balance: account.balance + U256::one(),
+                        ..account.clone()
+                    }),
+                };
+                (*address, changed_account)
+            })
+            .collect();
+        changes.insert(
+            AccountKeys::new().get_address(),
+            Some(AccountState {
+                balance: U256::one(),
+                nonce: 0,
+                multisig: None,
+            }),
+        );
+        let mut updated_accounts = accounts.clone();
+        for (address, account) in changes.iter() {
+            match account {
+                Some(account) => updated_accounts.insert(*address, account.clone()),
+                None => updated_accounts.remove(address),
+            };
+        }
+        let updated_root = StateTree::from_accounts(&updated_accounts).root();
+        assert_ne!(updated_root, state_root);
+        assert_eq!(tree.root_with(&changes), updated_root);
+        assert_eq!(tree.root(), state_root);
+        let mut updated_tree = tree.clone();
+        updated_tree.update(&changes);
+        assert_eq!(updated_tree.root(), updated_root);
+        for (address, account) in updated_accounts.iter() {
+            assert!(updated_tree.prove(address).verify(&updated_root, address, Some(account)));
+        }
+        let reverted_changes: HashMap<Address, Option<AccountState>> = changes
+            .keys()
+            .map(|address| (*address, accounts.get(address).cloned()))
+            .collect();
+        updated_tree.update(&reverted_changes);
+        assert_eq!(updated_tree.root(), state_root);
+    }
+
+    #[test]
+    fn test_merkle_proofs_follow_odd_leaf_rule() {
+        let mut sender_account = AccountKeys::new();
+        let receiver_public_key = AccountKeys::new().get_public_key();
+        let transactions: Vec<SignedTransaction> = (0..9)
+            .map(|nonce| {
+                let transaction = Transaction {
+                    from: Sender::Key(sender_account.get_public_key()),
+                    to: Recipient::Account(Address::from_public_key(&receiver_public_key)),
+                    amount: U256::from(1),
+                    fee: U256::from(1),
+                    nonce,
+                };
+                let signature = sender_account.sign_transaction(&transaction, 1);
+                SignedTransaction::new(transaction, signature)
+            })
+            .collect();
+        assert!(MerkleTree::build_tree(&[]).prove(0).is_none());
+
+        for leaf_count in 1..=transactions.len() {
+            let leaves = &transactions[..leaf_count];
+            let tree = MerkleTree::build_tree(leaves);
+            let root = tree.get_root().unwrap();
+            assert!(!tree.mutated);
+            assert!(tree.prove(leaf_count).is_none());
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.prove(index).unwrap();
+                assert!(verify_proof(&root, leaf, &proof), "{} of {}", index, leaf_count);
+                let other_leaf = &transactions[(index + 1) % transactions.len()];
+                assert!(!verify_proof(&root, other_leaf, &proof));
+                for tampered_proof in [
+                    MerkleProof { index: index ^ 1, ..proof.clone() },
+                    MerkleProof { leaf_count: index, ..proof.clone() },
+                    MerkleProof { siblings: [proof.siblings.clone(), vec![root]].concat(), ..proof.clone() },
+                ] {
+                    if tampered_proof != proof {
+                        assert!(!verify_proof(&root, leaf, &tampered_proof));
+                    }
+                }
+            }
+        }
+
+        // With three leaves the last one is paired with itself, so its proof
+        // has one sibling less, and [a, b, c, c] hashes to the same root.
+        let (a, b, c) = (&transactions[0], &transactions[1], &transactions[2]);
+        let tree = MerkleTree::build_tree(&transactions[..3]);
+        let root = tree.get_root().unwrap();
+        let proof = tree.prove(2).unwrap();
+        assert_eq!(proof.siblings.len(), 1);
+        let mutated_tree = MerkleTree::build_tree(&[a.clone(), b.clone(), c.clone(), c.clone()]);
+        assert_eq!(mutated_tree.get_root().unwrap(), root);
+        assert!(mutated_tree.mutated);
+
+        // Proofs of the duplicated leaf are rejected, even though they hash to the root.
+        let a_b_hash = MerkleTree::node_hash(&MerkleTree::leaf_hash(a), &MerkleTree::leaf_hash(b));
+        for index in [2, 3] {
+            let forged_proof = MerkleProof {
+                index,
+                leaf_count: 4,
+                siblings: vec![MerkleTree::leaf_hash(c), a_b_hash],
+            };
+            assert!(!verify_proof(&root, c, &forged_proof));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mutated_block_body_does_not_poison_its_header() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut receiving_chain = blockchain.clone();
+        for nonce in 0..3 {
+            let transaction = Transaction {
+                from: Sender::Key(sender_account.get_public_key()),
+                to: Recipient::Account(receiver_account.get_address()),
+                amount: U256::from(1),
+                fee: U256::from(1),
+                nonce,
+            };
+            let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+            network
+                .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+                .await
+                .unwrap();
+        }
+        let block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let block = blockchain.get_block(&block_hash).unwrap().clone();
+        assert_eq!(block.transactions.len(), 3);
+
+        // Every transaction can be shown to be in the block from the header alone.
+        let transactions = block.get_deseralized_transactions().unwrap();
+        for (index, transaction) in transactions.iter().enumerate() {
+            let proof = block.prove_transaction(index).unwrap().unwrap();
+            assert!(verify_proof(&block.header.merkle_root, transaction, &proof));
+        }
+        assert_eq!(block.prove_transaction(3), Ok(None));
+
+        // Repeating the last transaction keeps the Merkle root and so the
+        // header hash. The copy is rejected without marking the header invalid.
+        let mut mutated_block = block.clone();
+        mutated_block.transactions.push(block.transactions[2].clone());
+        assert_eq!(Block::hash_header(&mutated_block.header), block_hash);
+        assert_eq!(mutated_block.validate_merkle_root(), Err(BlockError::MutatedMerkleTree));
+        assert_eq!(
+            receiving_chain.add_block(mutated_block),
+            Err(BlockError::MutatedMerkleTree)
+        );
+        assert!(!receiving_chain.invalid_blocks.contains(&block_hash));
+        assert_eq!(receiving_chain.add_block(block), Ok(()));
+        assert_eq!(receiving_chain.current_longest_chain_latest_block_hash, block_hash);
+    }
+
+    #[tokio::test]
+    async fn test_light_client_follows_heaviest_header_chain() {
+        let mut sender_account = AccountKeys::new();
+        let spec = test_chain_spec(vec![GenesisAllocation {
+            address: sender_account.get_address(),
+            balance: U256::from(1000),
+        }]);
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        let mut light_client = LightClient::from_spec(&spec).unwrap();
+        assert_eq!(light_client.genesis_hash(), blockchain.genesis_hash());
+        let genesis_hash = blockchain.genesis_hash();
+        let mut network = Network::new();
+        let mut miner = Miner::new(&mut blockchain, Network::new());
+        let mut second_miner = Miner::new(&mut blockchain, Network::new());
+        network.add_miner(miner.clone());
+
+        let transaction = Transaction {
+            from: Sender::Key(sender_account.get_public_key()),
+            to: Recipient::Account(AccountKeys::new().get_address()),
+            amount: U256::from(10),
+            fee: U256::from(1),
+            nonce: 0,
+        };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let base_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let reorged_transaction = Transaction {
+            nonce: 1,
+            ..transaction.clone()
+        };
+        let signature = sender_account.sign_transaction(&reorged_transaction, blockchain.chain_id);
+        network
+            .send_transaction(reorged_transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let first_branch_hash = miner
+            .compute_next_block(&mut blockchain, base_hash)
+            .expect("Block must have been built");
+        let reorged_tx_hash = hash_transaction(&reorged_transaction, blockchain.chain_id);
+        assert_eq!(
+            blockchain.prove_transaction(&reorged_tx_hash).map(|(block_hash, ..)| block_hash),
+            Some(first_branch_hash)
+        );
+        let second_branch_hash = second_miner
+            .compute_next_block(&mut blockchain, base_hash)
+            .expect("Block must have been built");
+        let header_of = |blockchain: &Blockchain, hash: &BlockHash| {
+            blockchain.get_block(hash).unwrap().header.clone()
+        };
+
+        // Equal work keeps the first branch seen, more work takes over.
+        for hash in [base_hash, first_branch_hash, second_branch_hash] {
+            light_client.add_header(header_of(&blockchain, &hash)).unwrap();
+        }
+        assert_eq!(Block::hash_header(light_client.tip()), first_branch_hash);
+        let second_branch_tip_hash = second_miner
+            .compute_next_block(&mut blockchain, second_branch_hash)
+            .expect("Block must have been built");
+        light_client
+            .add_header(header_of(&blockchain, &second_branch_tip_hash))
+            .unwrap();
+        assert_eq!(Block::hash_header(light_client.tip()), second_branch_tip_hash);
+        for height in 0..=3 {
+            assert_eq!(
+                light_client.get_header_by_height(height).map(Block::hash_header),
+                blockchain.get_hash_by_height(height).copied()
+            );
+        }
+        assert!(!light_client.is_canonical(&first_branch_hash));
+        assert_eq!(
+            light_client.get_cumulative_work(&second_branch_tip_hash),
+            blockchain.hash_to_cumulative_work.get(&second_branch_tip_hash).copied()
+        );
+
+        // Transactions are checked with proofs from the full node.
+        let tx_hash = hash_transaction(&transaction, blockchain.chain_id);
+        let (block_hash, signed_transaction, proof) = blockchain.prove_transaction(&tx_hash).unwrap();
+        assert_eq!(block_hash, base_hash);
+        assert!(light_client.verify_transaction(&block_hash, &signed_transaction, &proof));
+        assert_eq!(light_client.confirmations(&block_hash), Some(3));
+        let mut altered_transaction = signed_transaction.clone();
+        altered_transaction.transaction.amount += U256::one();
+        assert!(!light_client.verify_transaction(&block_hash, &altered_transaction, &proof));
+        assert!(!light_client.verify_transaction(&first_branch_hash, &signed_transaction, &proof));
+        assert!(blockchain.prove_transaction(&TxHash::default()).is_none());
+        // The transaction of the abandoned branch left the index with it.
+        assert!(blockchain.prove_transaction(&reorged_tx_hash).is_none());
+
+        // Headers that break a consensus rule are rejected before being stored.
+        let next_hash = miner
+            .compute_next_block(&mut blockchain, second_branch_tip_hash)
+            .expect("Block must have been built");
+        let next_header = header_of(&blockchain, &next_hash);
+        let mut retargeted_header = next_header.clone();
+        retargeted_header.difficulty = U256::MAX;
+        assert!(matches!(
+            light_client.validate_header(&retargeted_header),
+            Err(BlockError::BadTarget { .. })
+        ));
+        let mut inflated_header = next_header.clone();
+        inflated_header.coinbase.reward += U256::one();
+        assert_eq!(light_client.validate_header(&inflated_header), Err(BlockError::BadCoinbase));
+        let mut unworked_header = next_header.clone();
+        while unworked_header.meets_target() {
+            unworked_header.nonce += 1;
+        }
+        assert!(matches!(
+            light_client.add_header(unworked_header),
+            Err(BlockError::InsufficientWork { .. })
+        ));
+        let mut stale_header = next_header.clone();
+        stale_header.timestamp = light_client.get_header(&genesis_hash).unwrap().timestamp;
+        assert!(matches!(
+            light_client.validate_header(&stale_header),
+            Err(BlockError::BadTimestamp(TimestampError::NotAfterMedianTimePast { .. }))
+        ));
+        let mut orphan_header = next_header.clone();
+        orphan_header.prev_hash = unknown_hash(7);
+        assert_eq!(
+            light_client.add_header(orphan_header),
+            Err(BlockError::UnknownParent { prev_hash: unknown_hash(7) })
+        );
+        let mut foreign_spec = spec.clone();
+        foreign_spec.chain_id += 1;
+        assert!(matches!(
+            light_client.add_header(foreign_spec.genesis_block().header),
+            Err(BlockError::GenesisMismatch { .. })
+        ));
+        light_client.add_header(next_header.clone()).unwrap();
+        assert_eq!(light_client.add_header(next_header), Err(BlockError::AlreadyKnown));
+        assert_eq!(Block::hash_header(light_client.tip()), next_hash);
+        assert_eq!(light_client.confirmations(&base_hash), Some(4));
+    }
+
+    #[test]
+    fn test_block_locator_finds_fork_below_light_tip() {
+        let mut spec = test_chain_spec(vec![GenesisAllocation {
+            address: AccountKeys::new().get_address(),
+            balance: U256::from(1000),
+        }]);
+        spec.initial_difficulty = U256::MAX;
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        let mut light_client = LightClient::from_spec(&spec).unwrap();
+        let genesis_hash = blockchain.genesis_hash();
+        let extend = |blockchain: &mut Blockchain, mut tip: BlockHash, count: u64, miner_address: Address| {
+            let mut hashes = Vec::new();
+            for _ in 0..count {
+                let height = blockchain.get_next_height(&tip).unwrap();
+                let mut block = Block::create_block(
+                    0,
+                    spec.genesis_timestamp + height,
+                    tip,
+                    height,
+                    blockchain.get_next_difficulty(&tip),
+                    &[],
+                    Coinbase::new(miner_address, blockchain.block_reward_after(&tip, height), &[]),
+                );
+                block.header.state_root = blockchain.compute_state_root(&block).unwrap();
+                let block = solve(block);
+                tip = Block::hash_header(&block.header);
+                blockchain.add_block(block).unwrap();
+                hashes.push(tip);
+            }
+            hashes
+        };
+
+        // The light client follows a branch that a heavier one later replaces
+        // from height 8, far below its tip.
+        let shared_hashes = extend(&mut blockchain, genesis_hash, 7, AccountKeys::new().get_address());
+        let first_branch_hashes = extend(&mut blockchain, shared_hashes[6], 23, AccountKeys::new().get_address());
+        for hash in shared_hashes.iter().chain(&first_branch_hashes) {
+            light_client
+                .add_header(blockchain.get_block(hash).unwrap().header.clone())
+                .unwrap();
+        }
+        let light_tip_hash = Block::hash_header(light_client.tip());
+        assert_eq!(light_client.tip().height, 30);
+        let second_branch_hashes = extend(&mut blockchain, shared_hashes[6], 38, AccountKeys::new().get_address());
+        assert_eq!(blockchain.tip().header.height, 45);
+
+        // Headers from above the light tip do not connect to anything it has.
+        let next_header = blockchain.get_block_by_height(31).unwrap().header.clone();
+        assert!(matches!(
+            light_client.validate_header(&next_header),
+            Err(BlockError::UnknownParent { .. })
+        ));
+
+        // The locator lets the full node find the last block both share.
+        let locator = light_client.block_locator(&light_tip_hash);
+        assert_eq!(locator.first(), Some(&light_tip_hash));
+        assert_eq!(locator.last(), Some(&genesis_hash));
+        assert!(locator.len() < 20);
+        assert_eq!(blockchain.locate_fork(&locator), 7);
+        assert_eq!(blockchain.locate_fork(&[unknown_hash(9)]), 0);
+        for header in blockchain.canonical_iter(8, 45).map(|block| block.header.clone()) {
+            light_client.add_header(header).unwrap();
+        }
+        assert_eq!(Block::hash_header(light_client.tip()), *second_branch_hashes.last().unwrap());
+        assert_eq!(
+            blockchain.locate_fork(&light_client.block_locator(second_branch_hashes.last().unwrap())),
+            45
+        );
+    }
+
+    #[test]
+    fn test_addresses_round_trip_through_bech32() {
+        let mut secret_key = [0u8; 32];
+        secret_key[31] = 1;
+        // The secret key 1 has the curve generator as public key.
+        let account = AccountKeys::from_signing_key(SigningKey::from_bytes(&secret_key.into()).unwrap());
+        let address = account.get_address();
+        assert_eq!(format!("{:?}", address), "Address(0f715baf5d4c2ed329785cef29e562f73488c8a2)");
+        assert_eq!(address, Address::from_public_key(&account.get_public_key()));
+
+        let text = address.to_bech32("bp");
+        assert_eq!(text, "bp1pac4ht6afshdx2tctnhjnetz7u6g3j9zp5zr6y");
+        assert_eq!(Address::from_bech32(&text, "bp"), Ok(address));
+        assert_eq!(Address::from_bech32(&text.to_ascii_uppercase(), "bp"), Ok(address));
+        assert_eq!(
+            Address::from_bech32("bp1pac4ht6afshdx2tctnhjnetz7u6g3j9zp5zR6Y", "bp"),
+            Err(AddressError::MixedCase)
+        );
+        assert_eq!(
+            Address::from_bech32(&text, "tbp"),
+            Err(AddressError::WrongPrefix {
+                expected: String::from("tbp"),
+                found: String::from("bp"),
+            })
+        );
+        // A single mistyped character breaks the checksum.
+        assert_eq!(
+            Address::from_bech32("bp1pac4ht6afshdx2tctnhjnetz7u6g3j9zp5zr6q", "bp"),
+            Err(AddressError::InvalidChecksum)
+        );
+        assert_eq!(
+            Address::from_bech32("bp1pac4ht6afshdx2tctnhjnetz7u6g3j9zp5zr6b", "bp"),
+            Err(AddressError::InvalidCharacter('b'))
+        );
+        assert_eq!(Address::from_bech32("pac4ht6a", "bp"), Err(AddressError::MissingSeparator));
+        assert!(!Address::is_valid_prefix("BP"));
+        assert!(!Address::is_valid_prefix(""));
+
+        let (blockchain, _, _, _, _) = setup();
+        assert_eq!(blockchain.format_address(&address), text);
+        assert_eq!(blockchain.parse_address(&text), Ok(address));
+    }
+
+    #[tokio::test]
+    async fn test_multisig_account_needs_threshold_signatures() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut keys: Vec<AccountKeys> = (1..=3u8)
+            .map(|secret_key_byte| {
+                let mut secret_key = [0u8; 32];
+                secret_key[31] = secret_key_byte;
+                AccountKeys::from_signing_key(SigningKey::from_bytes(&secret_key.into()).unwrap())
+            })
+            .collect();
+        let policy = MultisigPolicy::new(2, keys.iter().map(AccountKeys::get_public_key).collect());
+        let multisig_address = policy.address();
+        assert_eq!(
+            MultisigPolicy::new(2, policy.public_keys[..2].to_vec()).address().to_bech32("bp"),
+            "bp1867rjnpxwdsapqkv3tqhjt22ref76z9j0dyc9l"
+        );
+        assert_ne!(multisig_address, keys[0].get_address());
+
+        let sender_public_key = sender_account.get_public_key();
+        let register = |to: Recipient, nonce: u128| Transaction {
+            from: Sender::Key(sender_public_key),
+            to,
+            amount: U256::from(500),
+            fee: U256::from(1),
+            nonce,
+        };
+        let invalid_policies = [
+            MultisigPolicy::new(0, policy.public_keys.clone()),
+            MultisigPolicy::new(4, policy.public_keys.clone()),
+            MultisigPolicy::new(2, vec![policy.public_keys[0]; 2]),
+            MultisigPolicy::new(1, vec![policy.public_keys[0]; MAX_MULTISIG_KEYS + 1]),
+        ];
+        for invalid_policy in invalid_policies {
+            let transaction = register(Recipient::NewMultisig(invalid_policy), 0);
+            let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+            assert_eq!(
+                network
+                    .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+                    .await,
+                Err(TransactionError::InvalidMultisigPolicy)
+            );
+        }
+
+        // The sender funds the multisig account and registers its policy.
+        let registration = register(Recipient::NewMultisig(policy.clone()), 0);
+        let signature = sender_account.sign_transaction(&registration, blockchain.chain_id);
+        network
+            .send_transaction(registration.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let registration_block_hash = miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let multisig_account = blockchain.get_account(&multisig_address).unwrap().clone();
+        assert_eq!(multisig_account.balance, U256::from(500));
+        assert_eq!(multisig_account.multisig, Some(policy.clone()));
+        let proof = blockchain.prove_account(&multisig_address);
+        let state_root = blockchain.get_block(&registration_block_hash).unwrap().header.state_root;
+        assert!(proof.verify(&state_root, &multisig_address, Some(&multisig_account)));
+
+        let reregistration = register(Recipient::NewMultisig(policy.clone()), 1);
+        let signature = sender_account.sign_transaction(&reregistration, blockchain.chain_id);
+        assert_eq!(
+            network
+                .send_transaction(reregistration.serialize(), &signature, &mut miner, &mut blockchain)
+                .await,
+            Err(TransactionError::AlreadyRegistered)
+        );
+
+        let spend = |nonce: u128| Transaction {
+            from: Sender::Multisig(multisig_address),
+            to: Recipient::Account(receiver_account.get_address()),
+            amount: U256::from(100),
+            fee: U256::from(1),
+            nonce,
+        };
+        let transaction = spend(0);
+        let signatures: Vec<Signature> = keys
+            .iter_mut()
+            .map(|key| key.sign_transaction(&transaction, blockchain.chain_id))
+            .collect();
+        let outsider_signature = receiver_account
+            .clone()
+            .sign_transaction(&transaction, blockchain.chain_id);
+        // One signature short, out of key order, the same key twice, or
+        // signed by a key outside the policy.
+        let rejected_signature_sets = [
+            vec![signatures[0]],
+            vec![signatures[2], signatures[0]],
+            vec![signatures[0], signatures[0]],
+            vec![signatures[0], outsider_signature],
+            signatures.clone(),
+        ];
+        for rejected_signatures in rejected_signature_sets {
+            assert_eq!(
+                network
+                    .send_signed_transaction(
+                        SignedTransaction::with_signatures(transaction.clone(), rejected_signatures),
+                        &mut miner,
+                        &mut blockchain
+                    )
+                    .await,
+                Err(TransactionError::BadSignature)
+            );
+        }
+        let unregistered_policy = MultisigPolicy::new(1, policy.public_keys[..1].to_vec());
+        let unregistered_spend = Transaction {
+            from: Sender::Multisig(unregistered_policy.address()),
+            ..transaction.clone()
+        };
+        let unregistered_signature = keys[0].sign_transaction(&unregistered_spend, blockchain.chain_id);
+        assert_eq!(
+            network
+                .send_signed_transaction(
+                    SignedTransaction::new(unregistered_spend, unregistered_signature),
+                    &mut miner,
+                    &mut blockchain
+                )
+                .await,
+            Err(TransactionError::NotMultisig)
+        );
+        assert!(miner.mempool.is_empty());
+
+        network
+            .send_signed_transaction(
+                SignedTransaction::with_signatures(transaction, vec![signatures[0], signatures[2]]),
+                &mut miner,
+                &mut blockchain,
+            )
+            .await
+            .unwrap();
+        let spend_block_hash = miner
+            .compute_next_block(&mut blockchain, registration_block_hash)
+            .expect("Block must have been built");
+        assert_eq!(blockchain.get_balance(&multisig_address), U256::from(399));
+        assert_eq!(blockchain.get_balance(&receiver_account.get_address()), U256::from(100));
+
+        // Block validation cannot check a multisig signature without the
+        // account's policy, so a block short of signatures fails when applied.
+        let transaction = spend(1);
+        let signature = keys[1].sign_transaction(&transaction, blockchain.chain_id);
+        let signed_transaction = SignedTransaction::new(transaction, signature);
+        let block = solve(Block::create_block(
+            1,
+            blockchain.get_block(&spend_block_hash).unwrap().header.timestamp + 1,
+            spend_block_hash,
+            3,
+            blockchain.get_next_difficulty(&spend_block_hash),
+            std::slice::from_ref(&signed_transaction),
+            Coinbase::new(
+                miner.account_keys.get_address(),
+                blockchain.block_reward_at(3),
+                std::slice::from_ref(&signed_transaction),
+            ),
+        ));
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+        assert_eq!(
+            blockchain.add_block(block),
+            Err(BlockError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadSignature
+            })
+        );
+        assert_eq!(blockchain.get_balance(&multisig_address), U256::from(399));
+        assert_eq!(blockchain.current_longest_chain_latest_block_hash, spend_block_hash);
+    }
+
+    #[tokio::test]
+    async fn test_block_signatures_verify_in_parallel_and_from_cache() {
+        let (mut blockchain, mut network, mut miner, mut sender_account, receiver_account) =
+            setup();
+        let genesis_hash = blockchain.genesis_hash();
+        let mut transactions: Vec<SignedTransaction> = (0..12u128)
+            .map(|nonce| {
+                let transaction = Transaction {
+                    from: Sender::Key(sender_account.get_public_key()),
+                    to: Recipient::Account(receiver_account.get_address()),
+                    amount: U256::one(),
+                    fee: U256::one(),
+                    nonce,
+                };
+                let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+                SignedTransaction::new(transaction, signature)
+            })
+            .collect();
+        let block_over = |transactions: &[SignedTransaction]| {
+            Block::create_block(
+                1,
+                0,
+                genesis_hash,
+                1,
+                blockchain.get_next_difficulty(&genesis_hash),
+                transactions,
+                Coinbase::new(miner.account_keys.get_address(), U256::zero(), transactions),
+            )
+        };
+        let block = block_over(&transactions);
+        assert_eq!(block.validate_signatures(blockchain.chain_id, None), Ok(()));
+        assert_eq!(block.validate_signatures_serially(blockchain.chain_id), Ok(()));
+
+        // Both paths report the first forged signature, wherever threads meet it.
+        let forged_signature = receiver_account
+            .clone()
+            .sign_transaction(&transactions[9].transaction, blockchain.chain_id);
+        for index in [9, 4] {
+            transactions[index].signatures = vec![forged_signature];
+        }
+        let forged_block = block_over(&transactions);
+        let first_forged = Err(BlockError::InvalidTransaction {
+            index: 4,
+            error: TransactionError::BadSignature,
+        });
+        assert_eq!(forged_block.validate_signatures(blockchain.chain_id, None), first_forged);
+        assert_eq!(forged_block.validate_signatures_serially(blockchain.chain_id), first_forged);
+
+        // The cache is keyed by the signatures too: a cached transaction does
+        // not vouch for the same transaction under a forged signature.
+        let mut cache = SignatureCache::new(2);
+        let valid_transaction = block.get_deseralized_transactions().unwrap()[4].clone();
+        cache.insert(&valid_transaction, blockchain.chain_id);
+        assert!(cache.contains(&valid_transaction, blockchain.chain_id));
+        assert!(!cache.contains(&transactions[4], blockchain.chain_id));
+        assert!(!cache.contains(&valid_transaction, blockchain.chain_id + 1));
+        assert_eq!(
+            forged_block.validate_signatures(blockchain.chain_id, Some(&cache)),
+            first_forged
+        );
+        cache.insert(&transactions[4], blockchain.chain_id);
+        assert_eq!(
+            forged_block.validate_signatures(blockchain.chain_id, Some(&cache)),
+            Err(BlockError::InvalidTransaction {
+                index: 9,
+                error: TransactionError::BadSignature,
+            })
+        );
+        // The oldest entry makes room once the cache is full.
+        cache.insert(&transactions[0], blockchain.chain_id);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&valid_transaction, blockchain.chain_id));
+        // A removed and re-inserted entry counts as the newest one, so it is
+        // not evicted in place of older entries.
+        let mut reinserting_cache = SignatureCache::new(3);
+        reinserting_cache.insert(&transactions[0], blockchain.chain_id);
+        reinserting_cache.insert(&transactions[1], blockchain.chain_id);
+        reinserting_cache.remove(&transactions[0], blockchain.chain_id);
+        reinserting_cache.insert(&transactions[0], blockchain.chain_id);
+        reinserting_cache.insert(&transactions[2], blockchain.chain_id);
+        reinserting_cache.insert(&transactions[3], blockchain.chain_id);
+        assert_eq!(reinserting_cache.len(), 3);
+        assert!(!reinserting_cache.contains(&transactions[1], blockchain.chain_id));
+        assert!(reinserting_cache.contains(&transactions[0], blockchain.chain_id));
+        for _ in 0..10 {
+            reinserting_cache.remove(&transactions[0], blockchain.chain_id);
+            reinserting_cache.insert(&transactions[0], blockchain.chain_id);
+        }
+        reinserting_cache.insert(&transactions[4], blockchain.chain_id);
+        assert!(!reinserting_cache.contains(&transactions[2], blockchain.chain_id));
+        assert!(reinserting_cache.contains(&transactions[0], blockchain.chain_id));
+        let mut disabled_cache = SignatureCache::new(0);
+        disabled_cache.insert(&valid_transaction, blockchain.chain_id);
+        assert!(disabled_cache.is_empty());
+
+        // Mempool admission caches the signature; the block carrying the
+        // transaction skips it, then the entry is dropped.
+        let transaction = Transaction {
+            nonce: 0,
+            ..valid_transaction.transaction.clone()
+        };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        let signed_transaction = SignedTransaction::new(transaction.clone(), signature);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let signature_cache = blockchain.signature_cache.as_ref().unwrap();
+        assert!(signature_cache.contains(&signed_transaction, blockchain.chain_id));
+        // The cache only vouches for this node's checks and is not shared.
+        let serialized_blockchain = serde_json::to_string(&blockchain).unwrap();
+        assert!(!serialized_blockchain.contains("signature_cache"));
+        let restored_blockchain: Blockchain = serde_json::from_str(&serialized_blockchain).unwrap();
+        assert!(restored_blockchain.signature_cache.unwrap().is_empty());
+        miner
+            .compute_next_block(&mut blockchain, genesis_hash)
+            .expect("Block must have been built");
+        let signature_cache = blockchain.signature_cache.as_ref().unwrap();
+        assert!(!signature_cache.contains(&signed_transaction, blockchain.chain_id));
+        assert_eq!(blockchain.get_balance(&receiver_account.get_address()), U256::one());
+
+        // Without a cache, every signature is verified again.
+        blockchain.signature_cache = None;
+        let transaction = Transaction { nonce: 1, ..transaction };
+        let signature = sender_account.sign_transaction(&transaction, blockchain.chain_id);
+        network
+            .send_transaction(transaction.serialize(), &signature, &mut miner, &mut blockchain)
+            .await
+            .unwrap();
+        let tip = blockchain.current_longest_chain_latest_block_hash;
+        miner
+            .compute_next_block(&mut blockchain, tip)
+            .expect("Block must have been built");
+        assert_eq!(blockchain.get_balance(&receiver_account.get_address()), U256::from(2));
+    }
+
+    #[test]
+    fn test_parallel_proof_of_work_stops_on_first_solution() {
+        let header = Block::create_block(
+            0,
+            Blockchain::get_adjusted_time(),
+            BlockHash::GENESIS_PARENT,
+            1,
+            U256::MAX / 2000,
+            &[],
+            Coinbase::new(AccountKeys::new().get_address(), U256::zero(), &[]),
+        )
+        .header;
+        assert_eq!(PowEngine::new(0).threads, 1);
+
+        // A single worker tries every nonce in order, like the serial loop.
+        let mut expected_header = header.clone();
+        while !expected_header.meets_target() {
+            expected_header.nonce += 1;
+        }
+        let solution = PowEngine::new(1).solve(&header, header.timestamp);
+        assert_eq!(solution.header.nonce, expected_header.nonce);
+        assert_eq!(solution.hashes, expected_header.nonce + 1);
+        assert!(solution.hashrate() > 0.0);
+
+        for threads in [2, 4, 8] {
+            let solution = PowEngine::new(threads).solve(&header, header.timestamp);
+            assert!(solution.header.meets_target());
+            assert!(solution.header.timestamp >= header.timestamp);
+            assert_eq!(solution.header.prev_hash, header.prev_hash);
+            assert_eq!(solution.header.coinbase, header.coinbase);
+            assert!(solution.hashes >= 1);
+        }
+
+        // When every header meets the target, each worker stops after at
+        // most one hash.
+        let mut easy_header = header.clone();
+        easy_header.difficulty = U256::MAX;
+        let solution = PowEngine::new(4).solve(&easy_header, easy_header.timestamp);
+        assert!((1..=4).contains(&solution.hashes));
+        assert!(solution.header.nonce < 4);
+    }
 }
@@ -1,23 +1,21 @@
 pub use crate::blockchain::{
     self,
     account::AccountKeys,
-    block::{self, Block, Header, MerkleTree, Transaction},
-    utils::{convert_public_key_to_bytes, hash_transaction},
-    Blockchain,
+    block::{self, Block, Coinbase, Header, MerkleTree, Recipient, SignedTransaction, Transaction},
+    utils::hash_transaction,
+    Address, BlockError, BlockHash, Blockchain, HeaderChain, PowEngine, TransactionError,
 };
 use crate::log;
 use crate::mock::mock_network::Network;
-use primitive_types::U256;
-use std::time::SystemTime;
-use uint::FromStrRadixErr;
-use k256::{ecdsa::{signature::Verifier, Signature, VerifyingKey}};
+use k256::ecdsa::Signature;
 
 #[derive(Clone, PartialEq)]
 pub struct Miner {
-    pub mempool: Vec<Transaction>,
+    pub mempool: Vec<SignedTransaction>,
     pub account_keys: AccountKeys,
     pub network: Network,
     pub connected_peers: Vec<Miner>,
+    pub pow_engine: PowEngine,
 }
 
 impl Miner {
@@ -26,72 +24,98 @@ impl Miner {
         serialized_transaction: Vec<u8>,
         signature: &Signature,
         blockchain: &mut Blockchain,
-    ) {
-        let deserialized_transaction = Transaction::deseralize(&serialized_transaction);
-        if self.mempool.contains(&deserialized_transaction) {
-            return;
+    ) -> Result<(), TransactionError> {
+        let deserialized_transaction = Transaction::deseralize(&serialized_transaction)?;
+        let signed_transaction = SignedTransaction::new(deserialized_transaction, *signature);
+        self.on_signed_transaction_receive(signed_transaction, blockchain).await
+    }
+
+    /// Like `on_transaction_receive`, for transactions carrying any number
+    /// of signatures, such as those spending from a multisig account.
+    pub async fn on_signed_transaction_receive(
+        &mut self,
+        signed_transaction: SignedTransaction,
+        blockchain: &mut Blockchain,
+    ) -> Result<(), TransactionError> {
+        if self.mempool.contains(&signed_transaction) {
+            return Ok(());
         }
-        if self._validate_transaction(deserialized_transaction.clone(), signature, blockchain) {
-            let mut idx: usize = 0;
-            for mempool_transaction in self.mempool.iter() {
-                if mempool_transaction.fee > deserialized_transaction.fee {
-                    idx += 1;
-                }
+        self._validate_transaction(&signed_transaction, blockchain)?;
+        let mut idx: usize = 0;
+        for mempool_transaction in self.mempool.iter() {
+            if mempool_transaction.transaction.fee > signed_transaction.transaction.fee {
+                idx += 1;
             }
-            self.mempool.insert(idx, deserialized_transaction);
-            Box::pin(self.broadcast_transaction(serialized_transaction, signature, blockchain)).await;
         }
+        self.mempool.insert(idx, signed_transaction.clone());
+        Box::pin(self.broadcast_transaction(&signed_transaction, blockchain)).await;
+        Ok(())
     }
 
     pub async fn broadcast_transaction(
         &mut self,
-        serialized_transaction: Vec<u8>,
-        signature: &Signature,
+        signed_transaction: &SignedTransaction,
         blockchain: &mut Blockchain,
     ) {
         for miner in self.connected_peers.iter_mut() {
-            miner
-                .on_transaction_receive(serialized_transaction.clone(), signature, blockchain)
-                .await;
+            if let Err(err) = miner
+                .on_signed_transaction_receive(signed_transaction.clone(), blockchain)
+                .await
+            {
+                log::debug!(
+                    "Miner {:?} rejected transaction: {}",
+                    miner.account_keys.get_public_key(),
+                    err
+                );
+            }
         }
     }
 
     fn _validate_transaction(
         &mut self,
-        transaction: Transaction,
-        signature: &Signature,
+        signed_transaction: &SignedTransaction,
         blockchain: &mut Blockchain,
-    ) -> bool {
-        let public_key_from = transaction.public_key_from;
-        let verifying_key = VerifyingKey::from(&public_key_from);
-        if !(verifying_key
-            .verify(hash_transaction(&transaction).as_bytes(), signature)
-            .is_ok())
-        {
-            return false;
-        }
+    ) -> Result<(), TransactionError> {
+        let transaction = &signed_transaction.transaction;
+        let sender = transaction.sender();
+        let multisig = blockchain
+            .get_account(&sender)
+            .and_then(|account| account.multisig.as_ref());
+        signed_transaction.verify_signatures(blockchain.chain_id, multisig)?;
+        transaction.validate_recipient(
+            blockchain
+                .get_account(&transaction.recipient())
+                .and_then(|account| account.multisig.as_ref()),
+        )?;
 
-        let public_key = &transaction.public_key_from;
-        let mut account = blockchain.get_account(public_key);
+        let mut account = blockchain.get_account(&sender);
         if account.is_none() {
-            blockchain.create_account(&public_key);
-            account = blockchain.get_account(public_key);
+            blockchain.create_account(&sender);
+            account = blockchain.get_account(&sender);
         }
         let unwraped_account = account.expect("Account not existing");
         if transaction.nonce < unwraped_account.nonce {
-            return false;
+            return Err(TransactionError::BadNonce {
+                expected: unwraped_account.nonce,
+                found: transaction.nonce,
+            });
         }
-        if unwraped_account.balance < transaction.amount + transaction.fee {
-            return false;
+        let required = transaction.amount.saturating_add(transaction.fee);
+        if unwraped_account.balance < required {
+            return Err(TransactionError::InsufficientBalance {
+                balance: unwraped_account.balance,
+                required,
+            });
         }
-        return true;
+        blockchain.cache_verified_signature(signed_transaction);
+        Ok(())
     }
 
     pub fn compute_next_block(
         &mut self,
         blockchain: &mut Blockchain,
-        parent_block_hash: String,
-    ) -> Option<String> {
+        parent_block_hash: BlockHash,
+    ) -> Result<BlockHash, BlockError> {
         let max_transaction_count_in_block: usize = blockchain.max_transactions_per_block;
 
         let mut transactions_copy = {
@@ -102,15 +126,29 @@ impl Miner {
             };
             transactions_slice.to_vec()
         };
-        transactions_copy.sort_by(|a, b| a.nonce.cmp(&b.nonce));
+        transactions_copy.sort_by_key(|signed_transaction| signed_transaction.transaction.nonce);
         let mut temp_account_state = blockchain.accounts.clone();
 
         let mut i = 0;
         while i < transactions_copy.len() {
-            let processed_txn = &transactions_copy[i];
-            let public_key_bytes = &convert_public_key_to_bytes(&processed_txn.public_key_from);
-            let processed_txn_sender = temp_account_state.get_mut(public_key_bytes).unwrap();
-            if processed_txn.nonce != processed_txn_sender.nonce
+            let processed_txn = &transactions_copy[i].transaction;
+            // Only the first registration of a multisig address can go in.
+            let registration = match &processed_txn.to {
+                Recipient::NewMultisig(policy) => Some(policy.clone()),
+                Recipient::Account(_) => None,
+            };
+            let already_registered = registration.is_some()
+                && temp_account_state
+                    .get(&processed_txn.recipient())
+                    .is_some_and(|account| account.multisig.is_some());
+            // A sender unknown to the parent state cannot pay; drop the transaction.
+            let Some(processed_txn_sender) = temp_account_state.get_mut(&processed_txn.sender())
+            else {
+                transactions_copy.remove(i);
+                continue;
+            };
+            if already_registered
+                || processed_txn.nonce != processed_txn_sender.nonce
                 || processed_txn_sender.balance < processed_txn.amount + processed_txn.fee
             {
                 transactions_copy.remove(i);
@@ -118,53 +156,70 @@ impl Miner {
                 i += 1;
                 processed_txn_sender.nonce += 1;
                 processed_txn_sender.balance -= processed_txn.amount + processed_txn.fee;
+                if registration.is_some() {
+                    temp_account_state
+                        .entry(processed_txn.recipient())
+                        .or_default()
+                        .multisig = registration;
+                }
             }
         }
 
         let transaction_count = transactions_copy.len();
 
         let block: Block =
-            self._compute_next_block(transactions_copy, parent_block_hash.clone(), &blockchain);
-        if blockchain.add_block(block.clone(), self.account_keys.get_public_key()) {
-            if self.mempool.len() > transaction_count {
-                self.mempool = self.mempool[transaction_count..].to_vec();
-            } else {
-                self.mempool.clear();
-            }
-            self.broadcast_block(block.clone(), blockchain);
-            return Some(Block::hash_header(&block.header));
+            self._compute_next_block(transactions_copy, parent_block_hash, blockchain)?;
+        blockchain.add_block(block.clone())?;
+        if self.mempool.len() > transaction_count {
+            self.mempool = self.mempool[transaction_count..].to_vec();
+        } else {
+            self.mempool.clear();
         }
-        return None;
+        self.broadcast_block(block.clone(), blockchain);
+        Ok(Block::hash_header(&block.header))
     }
 
     fn _compute_next_block(
         &mut self,
-        transactions: Vec<Transaction>,
-        latest_block_hash: String,
+        transactions: Vec<SignedTransaction>,
+        latest_block_hash: BlockHash,
         blockchain: &Blockchain,
-    ) -> Block {
-        let mut timestamp: u64;
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(n) => timestamp = n.as_secs(),
-            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-        }
+    ) -> Result<Block, BlockError> {
+        // Blocks mined within the same second must still move past the median time past.
+        let min_timestamp = blockchain
+            .get_median_time_past(&latest_block_hash)
+            .map_or(0, |median_time_past| median_time_past + 1);
+        let timestamp: u64 = Blockchain::get_adjusted_time().max(min_timestamp);
         let nonce = 1;
-        let mut block: Block =
-            Block::create_block(nonce, timestamp, latest_block_hash.clone(), &transactions);
-        loop {
-            if let Ok(hash) = Block::hash_header(&block.header).parse::<U256>() {
-                if hash <= blockchain.difficulty {
-                    break;
-                }
-            }
-            block.header.nonce += 1;
-            match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                Ok(n) => timestamp = n.as_secs(),
-                Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-            }
-            block.header.timestamp = timestamp;
-        }
-        block
+        let height = blockchain
+            .get_next_height(&latest_block_hash)
+            .expect("Parent block to be known");
+        let difficulty = blockchain.get_next_difficulty(&latest_block_hash);
+        let coinbase = Coinbase::new(
+            self.account_keys.get_address(),
+            blockchain.block_reward_at(height),
+            &transactions,
+        );
+        let mut block: Block = Block::create_block(
+            nonce,
+            timestamp,
+            latest_block_hash,
+            height,
+            difficulty,
+            &transactions,
+            coinbase,
+        );
+        block.header.state_root = blockchain.compute_state_root(&block)?;
+        let solution = self.pow_engine.solve(&block.header, min_timestamp);
+        log::info!(
+            "Solved block at height {} with {} hashes on {} threads, {:.0} H/s",
+            height,
+            solution.hashes,
+            self.pow_engine.threads,
+            solution.hashrate()
+        );
+        block.header = solution.header;
+        Ok(block)
     }
 
     pub fn new(blockchain: &mut Blockchain, network: Network) -> Self {
@@ -173,51 +228,23 @@ impl Miner {
             account_keys: AccountKeys::new(),
             network,
             connected_peers: Vec::new(),
+            pow_engine: PowEngine::default(),
         };
-        blockchain.create_account(&miner.account_keys.get_public_key());
+        blockchain.create_account(&miner.account_keys.get_address());
         miner
     }
 
-    pub fn on_block_receive(&self, block: Block, blockchain: &mut Blockchain) {
-        if !self.validate_block(block.clone(), &blockchain) {
-            return;
-        }
-        blockchain.add_block(block, self.account_keys.get_public_key());
+    pub fn on_block_receive(
+        &self,
+        block: Block,
+        blockchain: &mut Blockchain,
+    ) -> Result<(), BlockError> {
+        blockchain.add_block(block)
     }
 
-    fn validate_block(&self, block: Block, blockchain: &Blockchain) -> bool {
-        let block_merkle_root = &block.header.merkle_root;
-        let deserialized_transactions = block.get_deseralized_transactions();
-        let recomputed_merkle_root = &MerkleTree::build_tree(&deserialized_transactions)
-            .root
-            .expect("Merkle root is None")
-            .value;
-        if block_merkle_root != recomputed_merkle_root {
-            return false;
-        }
-
-        if block.header.prev_hash == "" {
-            return true;
-        }
-
-        let block_hash = Block::hash_header(&block.header);
-        let block_hash_u256: Result<U256, FromStrRadixErr> = U256::from_str_radix(&block_hash, 16);
-        match block_hash_u256 {
-            Ok(hash) => {
-                if hash > blockchain.difficulty {
-                    return false;
-                }
-            }
-            Err(err) => {
-                log::error!(
-                    "Error: cannot parse block hash {}: encountered {}",
-                    &block_hash,
-                    err
-                );
-                return false;
-            }
-        }
-        return true;
+    /// Checks `block` against the miner's view of the chain without adding it.
+    pub fn validate_block(&self, block: &Block, blockchain: &Blockchain) -> Result<(), BlockError> {
+        blockchain.validate_block(block)
     }
 
     pub fn _add_connected_peer(&mut self, connected_peer: Miner) {
@@ -230,21 +257,20 @@ impl Miner {
     pub fn broadcast_block(&self, block: Block, blockchain: &mut Blockchain) {
         let block_hash = Block::hash_header(&block.header);
         let miners_block_recipients = blockchain.hash_to_miners_who_received_the_block.get_mut(&block_hash);
-        let public_key_bytes = convert_public_key_to_bytes(&self.account_keys.get_public_key());
+        let address = self.account_keys.get_address();
         match miners_block_recipients {
             Some(recipients) => {
-                recipients.push(public_key_bytes);
+                recipients.push(address);
             }
             None => {
-                let mut recipients: Vec<Vec<u8>> = Vec::new();
-                recipients.push(public_key_bytes);
-                blockchain.hash_to_miners_who_received_the_block.insert(block_hash.clone(), recipients);
+                let recipients: Vec<Address> = vec![address];
+                blockchain.hash_to_miners_who_received_the_block.insert(block_hash, recipients);
             }
         }
 
         for miner in self.connected_peers.iter() {
             if blockchain.hash_to_miners_who_received_the_block[&block_hash]
-                .contains(&convert_public_key_to_bytes(&miner.account_keys.get_public_key()))
+                .contains(&miner.account_keys.get_address())
             {
                 continue;
             }
@@ -253,7 +279,14 @@ impl Miner {
                 block,
                 miner.account_keys.get_public_key()
             );
-            miner.on_block_receive(block.clone(), blockchain);
+            if let Err(err) = miner.on_block_receive(block.clone(), blockchain) {
+                log::debug!(
+                    "Miner {:?} rejected block {}: {}",
+                    miner.account_keys.get_public_key(),
+                    block_hash,
+                    err
+                );
+            }
         }
     }
 }
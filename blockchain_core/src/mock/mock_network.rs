@@ -1,5 +1,5 @@
 use crate::blockchain::{
-    Blockchain,
+    Blockchain, SignedTransaction, TransactionError,
 };
 use crate::mock::mock_miner::Miner;
 use k256::ecdsa::Signature;
@@ -9,6 +9,12 @@ pub struct Network {
     pub miners: Vec<Miner>,
 }
 
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Network {
     pub fn new() -> Network {
         Network { miners: Vec::new() }
@@ -24,9 +30,20 @@ impl Network {
         signature: &Signature,
         connected_miner: &mut Miner,
         blockchain: &mut Blockchain,
-    ) {
+    ) -> Result<(), TransactionError> {
         connected_miner
             .on_transaction_receive(serialized_transaction, signature, blockchain)
-            .await;
+            .await
+    }
+
+    pub async fn send_signed_transaction(
+        &mut self,
+        signed_transaction: SignedTransaction,
+        connected_miner: &mut Miner,
+        blockchain: &mut Blockchain,
+    ) -> Result<(), TransactionError> {
+        connected_miner
+            .on_signed_transaction_receive(signed_transaction, blockchain)
+            .await
     }
 }
@@ -1,18 +1,31 @@
 mod p2p_node;
+mod peer_score;
 use anyhow::Result;
-use blockchain_core::{blockchain::{Blockchain}, miner::Miner, log};
-use clap::Parser;
+use blockchain_core::{blockchain::{utils::hash_transaction, Asert, Block, BlockError, Blockchain, ChainSpec, ChainSpecError, Decode, Encode, DifficultyAdjustment, EmissionSchedule, Header, HeaderChain, LightClient, Lwma, PeriodRetarget, PowEngine, SignedTransaction, StepAdjustment, TxHash}, miner::Miner, log};
+use clap::{Parser, ValueEnum};
 use futures::StreamExt;
 use libp2p::{core::Multiaddr, PeerId};
 use primitive_types::U256;
 use tokio::task::{spawn};
 use tracing_subscriber::EnvFilter;
-use std::{error::Error, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, thread};
-use serde_json;
+use peer_score::PeerScores;
+use std::{error::Error, path::PathBuf, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, thread};
 
+const CHAIN_ID: u64 = 1;
+const ADDRESS_PREFIX: &str = "bp";
+const GENESIS_TIMESTAMP: u64 = 1_750_000_000;
+const INITIAL_BLOCK_REWARD: u64 = 1000;
+const HALVING_INTERVAL: u64 = 210_000;
+const DIFFICULTY_DIVISOR: u32 = 20000;
 const TARGET_DURATION_BETWEEN_BLOCKS: u64 = 1;
 const MAX_TRANSACTIONS_PER_BLOCK: usize = 3;
 const BLOCKS_BETWEEN_DIFFICULTY_ADJUSTMENT: u64 = 10;
+const LWMA_WINDOW: u64 = 45;
+const ASERT_HALF_LIFE: u64 = 3600;
+const MAX_BLOCKS_PER_REQUEST: usize = 64;
+const MAX_HEADERS_PER_REQUEST: u64 = 512;
+const MAX_LOCATOR_HASHES: usize = 64;
+const MAX_ANCESTOR_REQUEST_ROUNDS: usize = 16;
 
 
 #[tokio::main]
@@ -23,6 +36,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .try_init();
 
     let opt = Opt::parse();
+    let chain_spec = match &opt.chain_spec {
+        Some(path) => ChainSpec::load(path)?,
+        None => default_chain_spec(opt.difficulty_algorithm),
+    };
+    log::info!("Using chain {} with genesis {}", chain_spec.chain_id, chain_spec.genesis_hash());
 
     let (mut node_client, mut node_events, node_event_loop) = p2p_node::new(opt.bootnode, opt.secret_key_seed).await?;
 
@@ -41,7 +59,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 }
     
     // In case the user wants to sync, dial with the bootnode
-    if let Some(true) = opt.sync {
+    if opt.sync == Some(true) || opt.light == Some(true) {
         let bootnode_id = opt.bootnode_id.expect("User to provide a bootnode_id");
         let bootnode_address = opt.bootnode_address.expect("User to provide a bootnode_address");
         node_client
@@ -53,29 +71,39 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
 
     if let Some(true) = opt.bootnode {
-        let difficulty_divisor: i32 = 20000;
-        let difficulty: U256 = U256::MAX / difficulty_divisor;
-        
         // run 2 tasks with a lock, 1 starts producing blocks, 1 listens to request, serializes blockchain and sends it 
-        let blockchain: Arc<Mutex<Blockchain>> = Arc::new(Mutex::new(Blockchain::create_blockchain(difficulty, TARGET_DURATION_BETWEEN_BLOCKS, MAX_TRANSACTIONS_PER_BLOCK, BLOCKS_BETWEEN_DIFFICULTY_ADJUSTMENT)));
+        let blockchain: Arc<Mutex<Blockchain>> = Arc::new(Mutex::new(Blockchain::from_spec(&chain_spec)?));
         
 
         // Missing: introduce an atomic bool to pause miner thread?
 
         let mut miner: Miner = Miner::new();
+        if let Some(threads) = opt.mining_threads {
+            miner.pow_engine = PowEngine::new(threads);
+        }
+        log::info!("Mining on {} threads", miner.pow_engine.threads);
+        log::info!(
+            "Mining rewards go to {}",
+            blockchain.lock().unwrap().format_address(&miner.account_keys.get_address())
+        );
         let miner_chain_reference = Arc::clone(&blockchain);
 
         let can_miner_run = Arc::new(AtomicBool::new(true));
         let can_miner_run_clone = Arc::clone(&can_miner_run);
+        let mut hash = chain_spec.genesis_hash();
         thread::spawn(move || {
-            let mut hash = String::from("");
             loop {
                 log::info!("Checking if miner can mine...");
                 if can_miner_run_clone.load(Ordering::Relaxed) {
                 let mut locked_miner_chain = miner_chain_reference.lock().expect("Write lock to be acquired");
                 log::info!("Lock acquired by miner");
-                hash = miner.compute_next_block(&mut *locked_miner_chain, hash).expect("Next block to be computed");
-                log::info!("Block computed with hash {:?}", hash);
+                match miner.compute_next_block(&mut locked_miner_chain, hash) {
+                    Ok(block_hash) => {
+                        hash = block_hash;
+                        log::info!("Block computed with hash {:?}", hash);
+                    }
+                    Err(err) => log::error!("Mined block was rejected: {}", err),
+                }
                 }
                 else {
                     log::info!("Cannot mine anymore, not acquiring lock and yielding");
@@ -90,9 +118,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     can_miner_run.store(false, Ordering::Relaxed);
                     log::info!("Received event for inbound request in main function");
                     let sync_chain_reference = Arc::clone(&blockchain);
-                    let locked_sync_chain = sync_chain_reference.lock().expect("Read lock to be acquired");
-                    log::info!("Locked sync chain in main function");
-                    let serialized_blockchain = serde_json::to_vec(&(*locked_sync_chain)).expect("Blockchain to be serialized");
+                    let serialized_blockchain = {
+                        let locked_sync_chain = sync_chain_reference.lock().expect("Read lock to be acquired");
+                        log::info!("Locked sync chain in main function");
+                        // Peers derive the genesis block from their own chain spec.
+                        let mut blocks: Vec<&Block> = locked_sync_chain
+                            .hash_to_block
+                            .values()
+                            .filter(|block| !block.header.prev_hash.is_genesis_parent())
+                            .collect();
+                        blocks.sort_by_key(|block| block.header.height);
+                        blocks.encode()
+                    };
                     log::info!("Serialized blockchain in main function");
                         node_client
                             .respond_blockchain_sync(serialized_blockchain, channel)
@@ -100,6 +137,43 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     log::info!("Responded to blockchain sync request in main function");
                     can_miner_run.store(true, Ordering::Relaxed);
                 }
+                Some(p2p_node::Event::InboundBlocksRequest { hashes, channel }) => {
+                    let serialized_blocks: Vec<Vec<u8>> = {
+                        let locked_chain = blockchain.lock().expect("Read lock to be acquired");
+                        hashes
+                            .iter()
+                            .take(MAX_BLOCKS_PER_REQUEST)
+                            .filter_map(|hash| locked_chain.get_block(hash))
+                            .map(|block| block.encode())
+                            .collect()
+                    };
+                    node_client.respond_blocks(serialized_blocks, channel).await;
+                }
+                Some(p2p_node::Event::InboundHeadersRequest { locator, channel }) => {
+                    let serialized_headers: Vec<Vec<u8>> = {
+                        let locked_chain = blockchain.lock().expect("Read lock to be acquired");
+                        let locator = &locator[..locator.len().min(MAX_LOCATOR_HASHES)];
+                        let from_height = locked_chain.locate_fork(locator) + 1;
+                        locked_chain
+                            .canonical_iter(from_height, from_height.saturating_add(MAX_HEADERS_PER_REQUEST - 1))
+                            .map(|block| block.header.encode())
+                            .collect()
+                    };
+                    node_client.respond_headers(serialized_headers, channel).await;
+                }
+                Some(p2p_node::Event::InboundTransactionProofRequest { tx_hash, channel }) => {
+                    let transaction_proof = {
+                        let locked_chain = blockchain.lock().expect("Read lock to be acquired");
+                        locked_chain
+                            .prove_transaction(&tx_hash)
+                            .map(|(block_hash, transaction, proof)| p2p_node::TransactionProof {
+                                block_hash,
+                                transaction: transaction.encode(),
+                                proof,
+                            })
+                    };
+                    node_client.respond_transaction_proof(transaction_proof, channel).await;
+                }
                 e => todo!("{:?}", e),
             }
         }
@@ -110,17 +184,213 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let serialized_chain = node_client.request_blockchain_sync(bootnode_id).await;
         match serialized_chain {
             Ok(chain) => {
-                let blockchain: Blockchain = serde_json::from_slice(&chain).expect("Blockchain to be deserialized");
+                let mut peer_scores = PeerScores::default();
+                let mut blockchain = import_blockchain(&chain, bootnode_id, &mut peer_scores, &chain_spec)?;
+                fetch_missing_ancestors(&mut node_client, &mut blockchain, bootnode_id, &mut peer_scores).await;
+                if peer_scores.is_banned(&bootnode_id) {
+                    log::error!("Bootnode {} served invalid blocks and was banned", bootnode_id);
+                }
+                log::info!("Synced canonical chain up to height {}", blockchain.tip().header.height);
                 log::info!("Retrived blockchain: {:?}", blockchain)
             },
             Err(e) => {
                 log::error!("{:?}", e)
             }
         }
+    } else if let Some(true) = opt.light {
+        let bootnode_id = opt.bootnode_id.expect("User to provide a bootnode_id");
+        let mut peer_scores = PeerScores::default();
+        let mut light_client = LightClient::from_spec(&chain_spec)?;
+        sync_headers(&mut node_client, &mut light_client, bootnode_id, &mut peer_scores).await;
+        if peer_scores.is_banned(&bootnode_id) {
+            log::error!("Bootnode {} served invalid headers and was banned", bootnode_id);
+        }
+        log::info!(
+            "Synced headers up to height {} ({})",
+            light_client.tip().height,
+            Block::hash_header(light_client.tip())
+        );
+        for tx_hash in &opt.verify_tx {
+            verify_transaction(&mut node_client, &light_client, bootnode_id, tx_hash).await;
+        }
     }
     Ok(())
 }
 
+/// Chain used when no `--chain-spec` file is given.
+fn default_chain_spec(difficulty_algorithm: DifficultyAlgorithmOpt) -> ChainSpec {
+    ChainSpec {
+        chain_id: CHAIN_ID,
+        address_prefix: String::from(ADDRESS_PREFIX),
+        genesis_timestamp: GENESIS_TIMESTAMP,
+        initial_difficulty: U256::MAX / DIFFICULTY_DIVISOR,
+        emission: EmissionSchedule::Halving {
+            initial_reward: U256::from(INITIAL_BLOCK_REWARD),
+            interval: HALVING_INTERVAL,
+        },
+        target_duration_between_blocks: TARGET_DURATION_BETWEEN_BLOCKS,
+        max_transactions_per_block: MAX_TRANSACTIONS_PER_BLOCK,
+        difficulty_algorithm: difficulty_algorithm.into(),
+        allocations: Vec::new(),
+    }
+}
+
+/// Rebuilds the chain from the encoded blocks served by `peer`, one block at a
+/// time so every block goes through local validation. Rejected blocks are logged and scored against the
+/// peer, and the import stops once the peer is banned.
+fn import_blockchain(
+    serialized_blockchain: &[u8],
+    peer: PeerId,
+    peer_scores: &mut PeerScores,
+    chain_spec: &ChainSpec,
+) -> Result<Blockchain, ChainSpecError> {
+    let mut blockchain = Blockchain::from_spec(chain_spec)?;
+    let mut blocks = match Vec::<Block>::decode(serialized_blockchain) {
+        Ok(blocks) => blocks,
+        Err(err) => {
+            let err = BlockError::Malformed(err.to_string());
+            log::error!("Peer {} served an unreadable blockchain: {}", peer, err);
+            peer_scores.penalize(peer, &err);
+            return Ok(blockchain);
+        }
+    };
+    blocks.sort_by_key(|block| block.header.height);
+    for block in blocks {
+        let block_hash = Block::hash_header(&block.header);
+        if let Err(err) = blockchain.add_block(block) {
+            log::warn!("Rejected block {} from peer {}: {}", block_hash, peer, err);
+            if peer_scores.penalize(peer, &err) {
+                break;
+            }
+        }
+    }
+    Ok(blockchain)
+}
+
+/// Requests the parents the orphan pool is waiting for from `peer`, round
+/// after round, until every orphan is connected, the peer stops returning
+/// blocks or the peer gets banned.
+async fn fetch_missing_ancestors(
+    node_client: &mut p2p_node::Client,
+    blockchain: &mut Blockchain,
+    peer: PeerId,
+    peer_scores: &mut PeerScores,
+) {
+    for _ in 0..MAX_ANCESTOR_REQUEST_ROUNDS {
+        let mut missing_ancestors = blockchain.get_missing_ancestors();
+        if missing_ancestors.is_empty() || peer_scores.is_banned(&peer) {
+            return;
+        }
+        missing_ancestors.truncate(MAX_BLOCKS_PER_REQUEST);
+        log::info!("Requesting {} missing ancestors from peer {}", missing_ancestors.len(), peer);
+        let serialized_blocks = match node_client.request_blocks(peer, missing_ancestors).await {
+            Ok(serialized_blocks) => serialized_blocks,
+            Err(err) => {
+                log::error!("Requesting missing ancestors failed: {:?}", err);
+                return;
+            }
+        };
+        if serialized_blocks.is_empty() {
+            return;
+        }
+        for serialized_block in serialized_blocks {
+            let result = match Block::decode(&serialized_block) {
+                Ok(block) => blockchain.add_block(block),
+                Err(err) => Err(BlockError::Malformed(err.to_string())),
+            };
+            if let Err(err) = result {
+                log::warn!("Rejected ancestor block from peer {}: {}", peer, err);
+                if peer_scores.penalize(peer, &err) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Follows the heaviest header chain of `peer`. Each request carries a
+/// block locator, so the peer answers from the last block both chains
+/// share, even after a reorg below the light client's tip. The next request
+/// starts from the last header received, until the peer has no more
+/// headers or gets banned.
+async fn sync_headers(
+    node_client: &mut p2p_node::Client,
+    light_client: &mut LightClient,
+    peer: PeerId,
+    peer_scores: &mut PeerScores,
+) {
+    let mut locator_hash = Block::hash_header(light_client.tip());
+    loop {
+        let locator = light_client.block_locator(&locator_hash);
+        let serialized_headers = match node_client.request_headers(peer, locator).await {
+            Ok(serialized_headers) => serialized_headers,
+            Err(err) => {
+                log::error!("Requesting headers failed: {:?}", err);
+                return;
+            }
+        };
+        let mut last_stored_hash = None;
+        for serialized_header in serialized_headers {
+            let result = Header::decode(&serialized_header)
+                .map_err(|err| BlockError::Malformed(err.to_string()))
+                .and_then(|header| {
+                    let hash = Block::hash_header(&header);
+                    match light_client.add_header(header) {
+                        Ok(()) | Err(BlockError::AlreadyKnown) => Ok(hash),
+                        Err(err) => Err(err),
+                    }
+                });
+            match result {
+                Ok(hash) => last_stored_hash = Some(hash),
+                Err(err) => {
+                    log::warn!("Rejected header from peer {}: {}", peer, err);
+                    if peer_scores.penalize(peer, &err) {
+                        return;
+                    }
+                }
+            }
+        }
+        match last_stored_hash {
+            Some(hash) if hash != locator_hash => locator_hash = hash,
+            _ => return,
+        }
+    }
+}
+
+/// Asks `peer` to prove that `tx_hash` is in its canonical chain and checks
+/// the proof against the light client's headers.
+async fn verify_transaction(
+    node_client: &mut p2p_node::Client,
+    light_client: &LightClient,
+    peer: PeerId,
+    tx_hash: &TxHash,
+) {
+    let transaction_proof = match node_client.request_transaction_proof(peer, *tx_hash).await {
+        Ok(Some(transaction_proof)) => transaction_proof,
+        Ok(None) => {
+            log::warn!("Peer {} does not know transaction {}", peer, tx_hash);
+            return;
+        }
+        Err(err) => {
+            log::error!("Requesting a proof of transaction {} failed: {:?}", tx_hash, err);
+            return;
+        }
+    };
+    let verified = SignedTransaction::decode(&transaction_proof.transaction).is_ok_and(|transaction| {
+        hash_transaction(&transaction.transaction, light_client.chain_id) == *tx_hash
+            && light_client.verify_transaction(&transaction_proof.block_hash, &transaction, &transaction_proof.proof)
+    });
+    match light_client.confirmations(&transaction_proof.block_hash) {
+        Some(confirmations) if verified => log::info!(
+            "Transaction {} is in block {} with {} confirmations",
+            tx_hash,
+            transaction_proof.block_hash,
+            confirmations
+        ),
+        _ => log::warn!("Peer {} served an invalid proof of transaction {}", peer, tx_hash),
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "In-memory Blockchain")]
 struct Opt {
@@ -139,9 +409,48 @@ struct Opt {
     #[arg(long)]
     sync: Option<bool>,
 
+    /// Sync headers only from the bootnode and follow the heaviest header chain.
+    #[arg(long)]
+    light: Option<bool>,
+
+    /// Transaction hash a light node asks the bootnode to prove; repeatable.
+    #[arg(long)]
+    verify_tx: Vec<TxHash>,
+
     #[arg(long)]
     bootnode_address: Option<Multiaddr>,
 
     #[arg(long)]
     bootnode_id: Option<PeerId>,
+
+    /// JSON chain spec; the built-in spec is used when omitted.
+    #[arg(long)]
+    chain_spec: Option<PathBuf>,
+
+    /// Difficulty algorithm of the built-in spec.
+    #[arg(long, value_enum, default_value_t = DifficultyAlgorithmOpt::Step)]
+    difficulty_algorithm: DifficultyAlgorithmOpt,
+
+    /// Worker threads searching for proof of work; one per core when omitted.
+    #[arg(long)]
+    mining_threads: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DifficultyAlgorithmOpt {
+    Step,
+    Bitcoin,
+    Lwma,
+    Asert,
+}
+
+impl From<DifficultyAlgorithmOpt> for DifficultyAdjustment {
+    fn from(algorithm: DifficultyAlgorithmOpt) -> Self {
+        match algorithm {
+            DifficultyAlgorithmOpt::Step => DifficultyAdjustment::Step(StepAdjustment { interval: BLOCKS_BETWEEN_DIFFICULTY_ADJUSTMENT }),
+            DifficultyAlgorithmOpt::Bitcoin => DifficultyAdjustment::PeriodRetarget(PeriodRetarget { interval: BLOCKS_BETWEEN_DIFFICULTY_ADJUSTMENT }),
+            DifficultyAlgorithmOpt::Lwma => DifficultyAdjustment::Lwma(Lwma { window: LWMA_WINDOW }),
+            DifficultyAlgorithmOpt::Asert => DifficultyAdjustment::Asert(Asert { half_life: ASERT_HALF_LIFE }),
+        }
+    }
 }
\ No newline at end of file
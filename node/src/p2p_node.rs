@@ -1,5 +1,5 @@
 use anyhow::{Result};
-use blockchain_core::{self, log};
+use blockchain_core::{self, blockchain::{BlockHash, MerkleProof, TxHash}, log};
 use futures::{
     channel::{mpsc, oneshot},
     prelude::*,
@@ -71,6 +71,27 @@ pub(crate) async fn new(
                 )],
                 request_response::Config::default(),
             ),
+            block_request: request_response::cbor::Behaviour::new(
+                [(
+                    StreamProtocol::new("/blockchain/blocks/1.0.0"),
+                    ProtocolSupport::Full,
+                )],
+                request_response::Config::default(),
+            ),
+            header_request: request_response::cbor::Behaviour::new(
+                [(
+                    StreamProtocol::new("/blockchain/headers/1.0.0"),
+                    ProtocolSupport::Full,
+                )],
+                request_response::Config::default(),
+            ),
+            proof_request: request_response::cbor::Behaviour::new(
+                [(
+                    StreamProtocol::new("/blockchain/proofs/1.0.0"),
+                    ProtocolSupport::Full,
+                )],
+                request_response::Config::default(),
+            ),
         })?
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
         .build();
@@ -156,16 +177,119 @@ impl Client {
             .await
             .expect("Command receiver not to be dropped.");
     }
+
+    /// Ask `peer` for the blocks with the given hashes, e.g. the missing
+    /// parents of orphan blocks.
+    pub(crate) async fn request_blocks(
+        &mut self,
+        peer: PeerId,
+        hashes: Vec<BlockHash>,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        log::info!("Sending command to request {} blocks", hashes.len());
+        self.sender
+            .send(Command::RequestBlocks {
+                peer,
+                hashes,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not be dropped.")
+    }
+
+    pub(crate) async fn respond_blocks(
+        &mut self,
+        serialized_blocks: Vec<Vec<u8>>,
+        channel: ResponseChannel<BlocksResponse>,
+    ) {
+        log::info!("Sending command to respond to blocks request");
+        self.sender
+            .send(Command::RespondBlocks { serialized_blocks, channel })
+            .await
+            .expect("Command receiver not to be dropped.");
+    }
+
+    /// Ask `peer` for the headers of its canonical chain following the last
+    /// block it shares with `locator`.
+    pub(crate) async fn request_headers(
+        &mut self,
+        peer: PeerId,
+        locator: Vec<BlockHash>,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        log::info!("Sending command to request headers after locator of {} hashes", locator.len());
+        self.sender
+            .send(Command::RequestHeaders {
+                peer,
+                locator,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not be dropped.")
+    }
+
+    pub(crate) async fn respond_headers(
+        &mut self,
+        serialized_headers: Vec<Vec<u8>>,
+        channel: ResponseChannel<HeadersResponse>,
+    ) {
+        log::info!("Sending command to respond to headers request");
+        self.sender
+            .send(Command::RespondHeaders { serialized_headers, channel })
+            .await
+            .expect("Command receiver not to be dropped.");
+    }
+
+    /// Ask `peer` to prove that the transaction `tx_hash` is in its canonical chain.
+    pub(crate) async fn request_transaction_proof(
+        &mut self,
+        peer: PeerId,
+        tx_hash: TxHash,
+    ) -> Result<Option<TransactionProof>, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        log::info!("Sending command to request a proof of transaction {}", tx_hash);
+        self.sender
+            .send(Command::RequestTransactionProof {
+                peer,
+                tx_hash,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not be dropped.")
+    }
+
+    pub(crate) async fn respond_transaction_proof(
+        &mut self,
+        transaction_proof: Option<TransactionProof>,
+        channel: ResponseChannel<TransactionProofResponse>,
+    ) {
+        log::info!("Sending command to respond to transaction proof request");
+        self.sender
+            .send(Command::RespondTransactionProof { transaction_proof, channel })
+            .await
+            .expect("Command receiver not to be dropped.");
+    }
 }
 
 
+type DialResultSender = oneshot::Sender<Result<(), Box<dyn Error + Send>>>;
+type BlockchainSyncResultSender = oneshot::Sender<Result<Vec<u8>, Box<dyn Error + Send>>>;
+type BlocksResultSender = oneshot::Sender<Result<Vec<Vec<u8>>, Box<dyn Error + Send>>>;
+type HeadersResultSender = oneshot::Sender<Result<Vec<Vec<u8>>, Box<dyn Error + Send>>>;
+type TransactionProofResultSender = oneshot::Sender<Result<Option<TransactionProof>, Box<dyn Error + Send>>>;
+
 pub(crate) struct EventLoop {
     swarm: Swarm<Behaviour>,
     command_receiver: mpsc::Receiver<Command>,
     event_sender: mpsc::Sender<Event>,
-    pending_dial: HashMap<PeerId, oneshot::Sender<Result<(), Box<dyn Error + Send>>>>,
-    pending_request_blockchain_sync:
-        HashMap<OutboundRequestId, oneshot::Sender<Result<Vec<u8>, Box<dyn Error + Send>>>>,
+    pending_dial: HashMap<PeerId, DialResultSender>,
+    pending_request_blockchain_sync: HashMap<OutboundRequestId, BlockchainSyncResultSender>,
+    pending_request_blocks: HashMap<OutboundRequestId, BlocksResultSender>,
+    pending_request_headers: HashMap<OutboundRequestId, HeadersResultSender>,
+    pending_request_transaction_proof: HashMap<OutboundRequestId, TransactionProofResultSender>,
 }
 
 impl EventLoop {
@@ -180,6 +304,9 @@ impl EventLoop {
             event_sender,
             pending_dial: Default::default(),
             pending_request_blockchain_sync: Default::default(),
+            pending_request_blocks: Default::default(),
+            pending_request_headers: Default::default(),
+            pending_request_transaction_proof: Default::default(),
         }
     }
 
@@ -242,6 +369,123 @@ impl EventLoop {
             )) => {
                 log::info!("Received event for response sent");
             }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequest(
+                request_response::Event::Message { message, .. },
+            )) => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    log::info!("Received event for inbound blocks request");
+                    self.event_sender
+                        .send(Event::InboundBlocksRequest {
+                            hashes: request.0,
+                            channel,
+                        })
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    let _ = self
+                        .pending_request_blocks
+                        .remove(&request_id)
+                        .expect("Request to still be pending.")
+                        .send(Ok(response.0));
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequest(
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                log::error!("Received event for blocks request outbound failure: {:?}", error);
+                let _ = self
+                    .pending_request_blocks
+                    .remove(&request_id)
+                    .expect("Request to still be pending.")
+                    .send(Err(Box::new(error)));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequest(_)) => {}
+            SwarmEvent::Behaviour(BehaviourEvent::HeaderRequest(
+                request_response::Event::Message { message, .. },
+            )) => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    log::info!("Received event for inbound headers request");
+                    self.event_sender
+                        .send(Event::InboundHeadersRequest {
+                            locator: request.locator,
+                            channel,
+                        })
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    let _ = self
+                        .pending_request_headers
+                        .remove(&request_id)
+                        .expect("Request to still be pending.")
+                        .send(Ok(response.0));
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::HeaderRequest(
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                log::error!("Received event for headers request outbound failure: {:?}", error);
+                let _ = self
+                    .pending_request_headers
+                    .remove(&request_id)
+                    .expect("Request to still be pending.")
+                    .send(Err(Box::new(error)));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::HeaderRequest(_)) => {}
+            SwarmEvent::Behaviour(BehaviourEvent::ProofRequest(
+                request_response::Event::Message { message, .. },
+            )) => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    log::info!("Received event for inbound transaction proof request");
+                    self.event_sender
+                        .send(Event::InboundTransactionProofRequest {
+                            tx_hash: request.0,
+                            channel,
+                        })
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    let _ = self
+                        .pending_request_transaction_proof
+                        .remove(&request_id)
+                        .expect("Request to still be pending.")
+                        .send(Ok(response.0));
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::ProofRequest(
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                log::error!("Received event for transaction proof request outbound failure: {:?}", error);
+                let _ = self
+                    .pending_request_transaction_proof
+                    .remove(&request_id)
+                    .expect("Request to still be pending.")
+                    .send(Err(Box::new(error)));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::ProofRequest(_)) => {}
             SwarmEvent::NewListenAddr { address, .. } => {
                 let local_peer_id = *self.swarm.local_peer_id();
                 log::info!(
@@ -253,18 +497,18 @@ impl EventLoop {
             SwarmEvent::ConnectionEstablished {
                 peer_id, endpoint, ..
             } => {
-                if endpoint.is_dialer() {
-                    if let Some(sender) = self.pending_dial.remove(&peer_id) {
-                        let _ = sender.send(Ok(()));
-                    }
+                if endpoint.is_dialer()
+                    && let Some(sender) = self.pending_dial.remove(&peer_id)
+                {
+                    let _ = sender.send(Ok(()));
                 }
             }
             SwarmEvent::ConnectionClosed { .. } => {}
             SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                if let Some(peer_id) = peer_id {
-                    if let Some(sender) = self.pending_dial.remove(&peer_id) {
-                        let _ = sender.send(Err(Box::new(error)));
-                    }
+                if let Some(peer_id) = peer_id
+                    && let Some(sender) = self.pending_dial.remove(&peer_id)
+                {
+                    let _ = sender.send(Err(Box::new(error)));
                 }
             }
             SwarmEvent::IncomingConnectionError { .. } => {}
@@ -326,6 +570,69 @@ impl EventLoop {
                     .send_response(channel, BlockchainSyncResponse(serialized_blockchain))
                     .expect("Connection to peer to be still open.");
             }
+            Command::RequestBlocks {
+                peer,
+                hashes,
+                sender,
+            } => {
+                log::info!("Sending blocks request from command");
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .block_request
+                    .send_request(&peer, BlocksRequest(hashes));
+                self.pending_request_blocks.insert(request_id, sender);
+            }
+            Command::RespondBlocks { serialized_blocks, channel } => {
+                log::info!("Sending blocks response from command");
+                self.swarm
+                    .behaviour_mut()
+                    .block_request
+                    .send_response(channel, BlocksResponse(serialized_blocks))
+                    .expect("Connection to peer to be still open.");
+            }
+            Command::RequestHeaders {
+                peer,
+                locator,
+                sender,
+            } => {
+                log::info!("Sending headers request from command");
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .header_request
+                    .send_request(&peer, HeadersRequest { locator });
+                self.pending_request_headers.insert(request_id, sender);
+            }
+            Command::RespondHeaders { serialized_headers, channel } => {
+                log::info!("Sending headers response from command");
+                self.swarm
+                    .behaviour_mut()
+                    .header_request
+                    .send_response(channel, HeadersResponse(serialized_headers))
+                    .expect("Connection to peer to be still open.");
+            }
+            Command::RequestTransactionProof {
+                peer,
+                tx_hash,
+                sender,
+            } => {
+                log::info!("Sending transaction proof request from command");
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .proof_request
+                    .send_request(&peer, TransactionProofRequest(tx_hash));
+                self.pending_request_transaction_proof.insert(request_id, sender);
+            }
+            Command::RespondTransactionProof { transaction_proof, channel } => {
+                log::info!("Sending transaction proof response from command");
+                self.swarm
+                    .behaviour_mut()
+                    .proof_request
+                    .send_response(channel, TransactionProofResponse(transaction_proof))
+                    .expect("Connection to peer to be still open.");
+            }
         }
     }
 }
@@ -350,19 +657,62 @@ enum Command {
         serialized_blockchain: Vec<u8>,
         channel: ResponseChannel<BlockchainSyncResponse>,
     },
+    RequestBlocks {
+        peer: PeerId,
+        hashes: Vec<BlockHash>,
+        sender: BlocksResultSender,
+    },
+    RespondBlocks {
+        serialized_blocks: Vec<Vec<u8>>,
+        channel: ResponseChannel<BlocksResponse>,
+    },
+    RequestHeaders {
+        peer: PeerId,
+        locator: Vec<BlockHash>,
+        sender: HeadersResultSender,
+    },
+    RespondHeaders {
+        serialized_headers: Vec<Vec<u8>>,
+        channel: ResponseChannel<HeadersResponse>,
+    },
+    RequestTransactionProof {
+        peer: PeerId,
+        tx_hash: TxHash,
+        sender: TransactionProofResultSender,
+    },
+    RespondTransactionProof {
+        transaction_proof: Option<TransactionProof>,
+        channel: ResponseChannel<TransactionProofResponse>,
+    },
 }
 
 #[derive(NetworkBehaviour)]
 struct Behaviour {
     request_response: request_response::cbor::Behaviour<BlockchainSyncRequest, BlockchainSyncResponse>,
     kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    block_request: request_response::cbor::Behaviour<BlocksRequest, BlocksResponse>,
+    header_request: request_response::cbor::Behaviour<HeadersRequest, HeadersResponse>,
+    proof_request: request_response::cbor::Behaviour<TransactionProofRequest, TransactionProofResponse>,
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Event {
     InboundRequest {
         channel: ResponseChannel<BlockchainSyncResponse>,
     },
+    InboundBlocksRequest {
+        hashes: Vec<BlockHash>,
+        channel: ResponseChannel<BlocksResponse>,
+    },
+    InboundHeadersRequest {
+        locator: Vec<BlockHash>,
+        channel: ResponseChannel<HeadersResponse>,
+    },
+    InboundTransactionProofRequest {
+        tx_hash: TxHash,
+        channel: ResponseChannel<TransactionProofResponse>,
+    },
 }
 
 // Simple blockchain sync protocol
@@ -370,3 +720,33 @@ pub(crate) enum Event {
 struct BlockchainSyncRequest();
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct BlockchainSyncResponse(Vec<u8>);
+
+// Blocks requested by hash, answered with the serialized blocks that were found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct BlocksRequest(Vec<BlockHash>);
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct BlocksResponse(Vec<Vec<u8>>);
+
+// Canonical headers following the last block shared with a block locator,
+// answered with the serialized headers, for light clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct HeadersRequest {
+    locator: Vec<BlockHash>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct HeadersResponse(Vec<Vec<u8>>);
+
+// Inclusion proof of a transaction requested by hash, `None` when it is not in
+// the canonical chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TransactionProofRequest(TxHash);
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TransactionProofResponse(Option<TransactionProof>);
+
+/// A serialized transaction and the Merkle proof placing it in `block_hash`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TransactionProof {
+    pub(crate) block_hash: BlockHash,
+    pub(crate) transaction: Vec<u8>,
+    pub(crate) proof: MerkleProof,
+}
@@ -0,0 +1,39 @@
+use blockchain_core::blockchain::{BlockError, TimestampError};
+use libp2p::PeerId;
+use std::collections::HashMap;
+
+/// Score from which a peer is considered faulty and its data is ignored.
+pub(crate) const BAN_SCORE: u32 = 100;
+
+/// Misbehaviour points accumulated by each peer for the blocks it sent us.
+#[derive(Debug, Default)]
+pub(crate) struct PeerScores {
+    scores: HashMap<PeerId, u32>,
+}
+
+impl PeerScores {
+    /// Adds the penalty for `error` to `peer` and returns true once the peer is banned.
+    pub(crate) fn penalize(&mut self, peer: PeerId, error: &BlockError) -> bool {
+        let score = self.scores.entry(peer).or_default();
+        *score = score.saturating_add(penalty(error));
+        *score >= BAN_SCORE
+    }
+
+    pub(crate) fn is_banned(&self, peer: &PeerId) -> bool {
+        self.scores.get(peer).is_some_and(|score| *score >= BAN_SCORE)
+    }
+}
+
+/// Honest peers may relay blocks we already have, blocks that raced ahead of
+/// their parent (kept as orphans until it arrives), orphans from a branch
+/// that has since become easier than ours or blocks from a slightly fast
+/// clock. Any other rejection means the peer produced or forwarded an
+/// invalid block.
+fn penalty(error: &BlockError) -> u32 {
+    match error {
+        BlockError::AlreadyKnown | BlockError::UnknownParent { .. } => 0,
+        BlockError::OrphanTargetTooEasy { .. }
+        | BlockError::BadTimestamp(TimestampError::TooFarInFuture { .. }) => 10,
+        _ => BAN_SCORE,
+    }
+}